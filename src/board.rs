@@ -0,0 +1,106 @@
+//! NUCLEO-F303K8 board support.
+//!
+//! Names the pins of the Arduino Nano headers CN3 (`D0`-`D12`) and CN4
+//! (`D13`, `A0`-`A7`) after the UM1956 user manual, so application code can
+//! say `board_pin!(reg, d12)` instead of `GpioB4`.
+//!
+//! # Solder bridges
+//!
+//! Some header pins share a net with another pin or with on-board hardware,
+//! depending on the solder bridges. The defaults of the F303K8 board are:
+//!
+//! * SB16 (ON) shorts `D5` (PB6) to `A5` (PA6), and SB18 (ON) shorts `D4`
+//!   (PB7) to `A4` (PA5), for I2C on `A4`/`A5` as on the Arduino Nano. While
+//!   they're closed only one pin of each pair may be driven, the other must
+//!   stay a floating input. Note that the pairs are PA5/PB7 and PA6/PB6, not
+//!   PA5/PB6 and PA6/PB7.
+//! * SB15 (ON) connects `D13` (PB3) to the green user LED LD3.
+//! * SB2 (ON) connects `A7` (PA2) to the ST-LINK virtual COM port TX. PA15,
+//!   the VCP RX, isn't on the headers and is connected through SB3.
+//! * SB6 and SB8 (ON) connect `D7` (PF0) and `D8` (PF1) to the headers. They
+//!   double as OSC_IN/OSC_OUT: with SB4 closed instead, PF0 gets the ST-LINK
+//!   MCO as an HSE bypass clock. SB17 (OFF) would route the same MCO to `A0`
+//!   (PA0).
+
+use drone_stm32_map::periph::gpio::pin::*;
+
+/// CN3 pin 2, USART1 RX.
+pub type D0 = GpioA10;
+/// CN3 pin 1, USART1 TX.
+pub type D1 = GpioA9;
+/// CN3 pin 5.
+pub type D2 = GpioA12;
+/// CN3 pin 6, TIM1_CH2N.
+pub type D3 = GpioB0;
+/// CN3 pin 7, I2C1 SDA. Shorted to [`A4`] through SB18.
+pub type D4 = GpioB7;
+/// CN3 pin 8, TIM16_CH1N. Shorted to [`A5`] through SB16.
+pub type D5 = GpioB6;
+/// CN3 pin 9, TIM1_CH3N.
+pub type D6 = GpioB1;
+/// CN3 pin 10, OSC_IN. Connected through SB6.
+pub type D7 = GpioF0;
+/// CN3 pin 11, OSC_OUT. Connected through SB8.
+pub type D8 = GpioF1;
+/// CN3 pin 12, TIM1_CH1.
+pub type D9 = GpioA8;
+/// CN3 pin 13, SPI chip select.
+pub type D10 = GpioA11;
+/// CN3 pin 14, SPI1 MOSI.
+pub type D11 = GpioB5;
+/// CN3 pin 15, SPI1 MISO.
+pub type D12 = GpioB4;
+/// CN4 pin 15, SPI1 SCK. Drives LD3 through SB15.
+pub type D13 = GpioB3;
+
+/// CN4 pin 12, ADC1_IN1.
+pub type A0 = GpioA0;
+/// CN4 pin 11, ADC1_IN2.
+pub type A1 = GpioA1;
+/// CN4 pin 10, ADC1_IN4.
+pub type A2 = GpioA3;
+/// CN4 pin 9, ADC2_IN1.
+pub type A3 = GpioA4;
+/// CN4 pin 8, ADC2_IN2. Shorted to [`D4`] through SB18.
+pub type A4 = GpioA5;
+/// CN4 pin 7, ADC2_IN3. Shorted to [`D5`] through SB16.
+pub type A5 = GpioA6;
+/// CN4 pin 6, ADC2_IN4.
+pub type A6 = GpioA7;
+/// CN4 pin 5, ADC1_IN3. Connected to the VCP TX through SB2.
+pub type A7 = GpioA2;
+
+/// Green user LED LD3.
+pub type Ld3 = D13;
+
+/// Acquires the [`GpioPin`](crate::drv::gpio_pin::GpioPin) of a header pin
+/// from `Regs`, e.g. `board_pin!(reg, d12)`.
+#[macro_export]
+macro_rules! board_pin {
+    ($reg:ident, d0) => { $crate::board_pin!(@new $reg, periph_gpio_a10) };
+    ($reg:ident, d1) => { $crate::board_pin!(@new $reg, periph_gpio_a9) };
+    ($reg:ident, d2) => { $crate::board_pin!(@new $reg, periph_gpio_a12) };
+    ($reg:ident, d3) => { $crate::board_pin!(@new $reg, periph_gpio_b0) };
+    ($reg:ident, d4) => { $crate::board_pin!(@new $reg, periph_gpio_b7) };
+    ($reg:ident, d5) => { $crate::board_pin!(@new $reg, periph_gpio_b6) };
+    ($reg:ident, d6) => { $crate::board_pin!(@new $reg, periph_gpio_b1) };
+    ($reg:ident, d7) => { $crate::board_pin!(@new $reg, periph_gpio_f0) };
+    ($reg:ident, d8) => { $crate::board_pin!(@new $reg, periph_gpio_f1) };
+    ($reg:ident, d9) => { $crate::board_pin!(@new $reg, periph_gpio_a8) };
+    ($reg:ident, d10) => { $crate::board_pin!(@new $reg, periph_gpio_a11) };
+    ($reg:ident, d11) => { $crate::board_pin!(@new $reg, periph_gpio_b5) };
+    ($reg:ident, d12) => { $crate::board_pin!(@new $reg, periph_gpio_b4) };
+    ($reg:ident, d13) => { $crate::board_pin!(@new $reg, periph_gpio_b3) };
+    ($reg:ident, a0) => { $crate::board_pin!(@new $reg, periph_gpio_a0) };
+    ($reg:ident, a1) => { $crate::board_pin!(@new $reg, periph_gpio_a1) };
+    ($reg:ident, a2) => { $crate::board_pin!(@new $reg, periph_gpio_a3) };
+    ($reg:ident, a3) => { $crate::board_pin!(@new $reg, periph_gpio_a4) };
+    ($reg:ident, a4) => { $crate::board_pin!(@new $reg, periph_gpio_a5) };
+    ($reg:ident, a5) => { $crate::board_pin!(@new $reg, periph_gpio_a6) };
+    ($reg:ident, a6) => { $crate::board_pin!(@new $reg, periph_gpio_a7) };
+    ($reg:ident, a7) => { $crate::board_pin!(@new $reg, periph_gpio_a2) };
+    ($reg:ident, ld3) => { $crate::board_pin!($reg, d13) };
+    (@new $reg:ident, $periph:ident) => {
+        $crate::drv::gpio_pin::GpioPin::new(::drone_stm32_map::periph::gpio::$periph!($reg))
+    };
+}
@@ -6,5 +6,8 @@ pub const SWO_BAUD_RATE: usize = 115_200;
 // HSI internal 8 MHz RC Oscillator.
 pub const HSI_CLK: u32 = 8_000_000;
 
-// HSE high speed external clock (not present on Nucleo-144)
-pub const HSE_CLK: u32 = 48_000_000;
+// LSE external 32.768 kHz resonator.
+pub const LSE_CLK: u32 = 32_768;
+
+// MCO output of the ST-LINK, usable as HSE in bypass mode (SB4 ON, SB6 OFF).
+pub const HSE_STLINK_MCO_CLK: u32 = 8_000_000;
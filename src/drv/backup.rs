@@ -0,0 +1,135 @@
+//! RTC backup registers.
+//!
+//! Sixteen 32-bit registers in the backup domain, kept across system resets
+//! as long as VDD is present. They are cleared by a backup domain reset.
+//!
+//! Register 0 holds [`BACKUP_MAGIC`] once anything was stored, so values
+//! left over from another firmware aren't mistaken for ours. Registers 1 to
+//! 15 are available as typed [`BackupSlot`]s.
+
+use crate::periph::backup::BackupPeriph;
+use crate::tasks::root::SystemRes;
+use core::marker::PhantomData;
+use drone_cortexm::reg::prelude::*;
+
+/// Marks the backup registers as written by this firmware.
+pub const BACKUP_MAGIC: u32 = 0xF303_B0B0;
+
+/// A value which fits in a backup register.
+pub trait BackupValue: Sized {
+    /// Converts the value to the register contents.
+    fn to_word(&self) -> u32;
+
+    /// Converts the register contents back, or returns `None` if they don't
+    /// hold a valid value.
+    fn from_word(word: u32) -> Option<Self>;
+}
+
+impl BackupValue for u32 {
+    fn to_word(&self) -> u32 {
+        *self
+    }
+
+    fn from_word(word: u32) -> Option<Self> {
+        Some(word)
+    }
+}
+
+/// A backup register holding a `T`.
+pub struct BackupSlot<T: BackupValue> {
+    index: usize,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T: BackupValue> BackupSlot<T> {
+    /// Creates a slot for backup register `index`, 1 to 15. Register 0 is
+    /// reserved for [`BACKUP_MAGIC`].
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of range. In a `const` this fails the build.
+    pub const fn new(index: usize) -> Self {
+        assert!(index >= 1 && index <= 15, "backup slot index must be 1 to 15");
+        Self { index, _value: PhantomData }
+    }
+}
+
+/// Backup registers driver.
+pub struct Backup {
+    periph: BackupPeriph,
+}
+
+macro_rules! bkp {
+    ($self:ident, $index:expr, $($op:tt)*) => {
+        match $index {
+            0 => $self.periph.rtc_bkp0r.$($op)*,
+            1 => $self.periph.rtc_bkp1r.$($op)*,
+            2 => $self.periph.rtc_bkp2r.$($op)*,
+            3 => $self.periph.rtc_bkp3r.$($op)*,
+            4 => $self.periph.rtc_bkp4r.$($op)*,
+            5 => $self.periph.rtc_bkp5r.$($op)*,
+            6 => $self.periph.rtc_bkp6r.$($op)*,
+            7 => $self.periph.rtc_bkp7r.$($op)*,
+            8 => $self.periph.rtc_bkp8r.$($op)*,
+            9 => $self.periph.rtc_bkp9r.$($op)*,
+            10 => $self.periph.rtc_bkp10r.$($op)*,
+            11 => $self.periph.rtc_bkp11r.$($op)*,
+            12 => $self.periph.rtc_bkp12r.$($op)*,
+            13 => $self.periph.rtc_bkp13r.$($op)*,
+            14 => $self.periph.rtc_bkp14r.$($op)*,
+            15 => $self.periph.rtc_bkp15r.$($op)*,
+            _ => unreachable!(),
+        }
+    };
+}
+
+impl Backup {
+    /// Creates a new [`Backup`].
+    #[inline]
+    pub fn new(periph: BackupPeriph) -> Self {
+        Self { periph }
+    }
+
+    /// Releases the peripheral.
+    #[inline]
+    pub fn free(self) -> BackupPeriph {
+        self.periph
+    }
+
+    /// Returns `true` if the registers were written by this firmware.
+    pub fn is_valid(&self) -> bool {
+        self.read(0) == BACKUP_MAGIC
+    }
+
+    /// Returns the value in `slot`, or `None` if there is none.
+    pub fn load<T: BackupValue>(&self, slot: &BackupSlot<T>) -> Option<T> {
+        if self.is_valid() {
+            T::from_word(self.read(slot.index))
+        } else {
+            None
+        }
+    }
+
+    /// Stores `value` in `slot`.
+    pub fn store<T: BackupValue>(&self, res: &SystemRes, slot: &BackupSlot<T>, value: &T) {
+        res.rcc.set_apb1enr_pwren();
+        res.rcc.set_pwr_cr_dbp();
+        if !self.is_valid() {
+            // Don't let leftovers in other slots become valid.
+            for index in 1..=15 {
+                self.write(index, 0);
+            }
+            self.write(0, BACKUP_MAGIC);
+        }
+        self.write(slot.index, value.to_word());
+        res.rcc.clear_apb1enr_pwren();
+    }
+
+    fn read(&self, index: usize) -> u32 {
+        bkp!(self, index, load().bkp())
+    }
+
+    fn write(&self, index: usize, word: u32) {
+        bkp!(self, index, store(|r| r.write_bkp(word)))
+    }
+}
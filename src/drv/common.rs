@@ -14,11 +14,24 @@
 use crate::dma::mux::DmamuxChEn;
 #[cfg(feature = "dma")]
 use crate::dma::DmaChEn;
+use core::ptr;
+use drone_core::reg::{tag::RegTag, Reg, RegField};
 #[allow(unused_imports)]
 use drone_cortexm::thr::prelude::*;
 #[cfg(feature = "dma")]
 use drone_stm32_map::periph::dma::ch::DmaChMap;
 
+/// Clears the flag `field` of a write-1-to-clear register.
+///
+/// Only the bit of `field` is written. A read-modify-write or a bit-band
+/// write of such a register would clear every other flag that is set.
+#[inline]
+pub fn clear_w1c<T: RegTag, F: RegField<T>>(_field: &F) {
+    unsafe {
+        ptr::write_volatile(<F::Reg as Reg<T>>::ADDRESS as *mut u32, 1 << F::OFFSET);
+    }
+}
+
 /// Driver reset and clock control.
 pub trait DrvRcc {
     /// Resets the peripheral.
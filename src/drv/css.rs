@@ -0,0 +1,75 @@
+//! Clock Security System.
+//!
+//! When the HSE fails while the CSS is on, the hardware switches SYSCLK to
+//! HSI, stops the HSE and the PLL, and raises the NMI.
+
+use crate::consts::HSI_CLK;
+use crate::periph::css::CssPeriph;
+use crate::thr;
+use drone_core::log;
+use drone_core::reg::tag::{Crt, Srt};
+use drone_cortexm::{fib, reg::prelude::*, swo, thr::prelude::*};
+use drone_stm32_map::reg;
+use futures::prelude::*;
+
+/// An event published when the HSE failed and the system fell back to HSI.
+#[derive(Debug)]
+pub struct ClockFault;
+
+/// CSS driver.
+pub struct Css {
+    rcc_cr_csson: reg::rcc::cr::Csson<Srt>,
+    rcc_cir_cssf: reg::rcc::cir::Cssf<Crt>,
+    rcc_cir_cssc: reg::rcc::cir::Cssc<Crt>,
+}
+
+impl Css {
+    /// Creates a new [`Css`].
+    #[inline]
+    pub fn new(periph: CssPeriph) -> Self {
+        let CssPeriph {
+            rcc_cr_csson,
+            rcc_cir_cssf,
+            rcc_cir_cssc,
+        } = periph;
+        Self {
+            rcc_cr_csson,
+            rcc_cir_cssf: rcc_cir_cssf.into_copy(),
+            rcc_cir_cssc: rcc_cir_cssc.into_copy(),
+        }
+    }
+
+    /// Enables the clock detector. Call it once the HSE is ready.
+    pub fn enable(&self) {
+        self.rcc_cr_csson.set_bit();
+    }
+
+    /// Disables the clock detector.
+    pub fn disable(&self) {
+        self.rcc_cr_csson.clear_bit();
+    }
+
+    /// Creates a stream of HSE failures, handled by the `nmi` thread.
+    ///
+    /// The handler clears the NMI source and adjusts the SWO prescaler to
+    /// HSI right away. Recomputing the clock tree is left to the consumer of
+    /// the stream. The stream should be created once and kept alive, so that
+    /// a failure is always acknowledged.
+    pub fn create_fault_stream(
+        &self,
+        nmi: thr::Nmi,
+    ) -> impl Stream<Item = ClockFault> + Send + Sync {
+        let cssf = self.rcc_cir_cssf;
+        let cssc = self.rcc_cir_cssc;
+        nmi.add_saturating_pulse_stream(fib::new_fn(move || {
+            if cssf.read_bit() {
+                cssc.set_bit();
+                swo::update_prescaler(HSI_CLK / log::baud_rate!() - 1);
+                fib::Yielded(Some(1))
+            } else {
+                fib::Yielded(None)
+            }
+        }))
+        .map(|_| ClockFault)
+    }
+}
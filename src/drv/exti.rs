@@ -1,12 +1,40 @@
 use core::num::NonZeroUsize;
 //use displaydoc::Display;
-use crate::drv::exti_diverged::ExtiDiverged;
+use crate::{
+    drv::{common::clear_w1c, exti_diverged::ExtiDiverged},
+    thr,
+};
 use drone_cortexm::{fib, fib::Fiber, reg::prelude::*, thr::prelude::*};
 use drone_stm32_map::periph::exti::{
-    ExtiFtsrFt, ExtiMap, ExtiPeriph, ExtiPrPif, ExtiRtsrRt, ExtiSwierSwi, SyscfgExticrExti,
+    Exti0, Exti1, Exti10, Exti11, Exti12, Exti13, Exti14, Exti15, Exti2, Exti3, Exti4, Exti5,
+    Exti6, Exti7, Exti8, Exti9, ExtiFtsrFt, ExtiMap, ExtiPeriph, ExtiPrPif, ExtiRtsrRt,
+    ExtiSwierSwi, SyscfgExticrExti,
 };
 use futures::prelude::*;
 
+/// The interrupt an EXTI line is routed to.
+///
+/// Lines 5 to 9 and 10 to 15 share one interrupt each. Several [`ExtiDrv`]
+/// can be set up on a shared interrupt, each one only takes the events of its
+/// own line.
+pub trait ExtiLineInt<Exti: ExtiMap>: IntToken {}
+
+macro_rules! exti_line_int {
+    ($($int:ident: $($exti:ident),*;)*) => {
+        $($(impl ExtiLineInt<$exti> for thr::$int {})*)*
+    };
+}
+
+exti_line_int! {
+    Exti0: Exti0;
+    Exti1: Exti1;
+    Exti2Tsc: Exti2;
+    Exti3: Exti3;
+    Exti4: Exti4;
+    Exti95: Exti5, Exti6, Exti7, Exti8, Exti9;
+    Exti1510: Exti10, Exti11, Exti12, Exti13, Exti14, Exti15;
+}
+
 /// EXTI stream overflow
 #[derive(Debug)]
 pub struct ExtiOverflow;
@@ -14,7 +42,7 @@ pub struct ExtiOverflow;
 /// EXTI setup.
 pub struct ExtiSetup<
     Exti: ExtiMap + SyscfgExticrExti + ExtiRtsrRt + ExtiFtsrFt + ExtiSwierSwi + ExtiPrPif,
-    ExtiInt: IntToken,
+    ExtiInt: ExtiLineInt<Exti>,
 > {
     /// EXTI peripheral.
     pub exti: ExtiPeriph<Exti>,
@@ -34,7 +62,7 @@ pub struct ExtiSetup<
 /// EXTI driver.
 pub struct ExtiDrv<
     Exti: ExtiMap + SyscfgExticrExti + ExtiRtsrRt + ExtiFtsrFt + ExtiSwierSwi + ExtiPrPif,
-    ExtiInt: IntToken,
+    ExtiInt: ExtiLineInt<Exti>,
 > {
     exti: ExtiDiverged<Exti>,
     exti_int: ExtiInt,
@@ -42,7 +70,7 @@ pub struct ExtiDrv<
 
 impl<
         Exti: ExtiMap + SyscfgExticrExti + ExtiRtsrRt + ExtiFtsrFt + ExtiSwierSwi + ExtiPrPif,
-        ExtiInt: IntToken,
+        ExtiInt: ExtiLineInt<Exti>,
     > ExtiDrv<Exti, ExtiInt>
 {
     /// Sets up a new [`ExtiDrv`] from `setup` values.
@@ -75,12 +103,24 @@ impl<
             .add_pulse_try_stream(|| Err(ExtiOverflow), self.new_fib())
     }
 
+    /// Raises an event on the line from software, as if the selected edge
+    /// had occurred on the pin. The event goes through the streams like any
+    /// other one.
+    #[inline]
+    pub fn trigger_software(&self) {
+        // Cleared along with the pending bit by the stream fibers.
+        self.exti.exti_swier_swi.set_bit();
+    }
+
     fn new_fib<R>(&self) -> impl Fiber<Input = (), Yield = Option<usize>, Return = R> {
         let exti_pr_pif = self.exti.exti_pr_pif;
         fib::new_fn(move || {
+            // On a shared interrupt, the fiber of every line sharing it runs,
+            // and each one only takes its own pending bit. The bit is cleared
+            // alone, so the pending bits of the other lines survive.
             if exti_pr_pif.read_bit() {
                 // selected trigger request occurred
-                exti_pr_pif.set_bit();
+                clear_w1c(&exti_pr_pif);
                 fib::Yielded(Some(1))
             } else {
                 fib::Yielded(None)
@@ -1,7 +1,221 @@
 //! Embedded Flash memory.
+//!
+//! The 64 KB of flash are organized in 32 pages of 2 KB. Flash is written in
+//! half-words, and only after the containing page was erased. The HSI must
+//! be on while programming.
+//!
+//! The option bytes live in their own block at [`OPTION_BYTES_BASE`]. The
+//! STM32F303 has no brown-out reset level option, its POR/PDR thresholds
+//! are fixed.
 
 use crate::periph::flash::FlashPeriph;
+use crate::sys::clock_config::AhbPrescaler;
+use core::{convert::Infallible, intrinsics, ptr};
+use drone_core::reg::{tag::Srt, Reg};
 use drone_cortexm::reg::prelude::*;
+use drone_stm32_map::reg;
+
+/// Start address of the flash memory.
+pub const FLASH_BASE: u32 = 0x0800_0000;
+/// Size of the flash memory in bytes.
+pub const FLASH_SIZE: u32 = 64 * 1024;
+/// Size of a flash page in bytes.
+pub const FLASH_PAGE_SIZE: u32 = 2 * 1024;
+/// Highest SYSCLK at which the half-cycle access may be used.
+pub const HALF_CYCLE_MAX_SYSCLK: u32 = 8_000_000;
+/// The prefetch buffer may only be switched while SYSCLK is below this.
+pub const PREFETCH_SWITCH_MAX_SYSCLK: u32 = 24_000_000;
+/// Start address of the option bytes.
+pub const OPTION_BYTES_BASE: u32 = 0x1FFF_F800;
+/// Has to be passed to [`Flash::mass_erase`] to confirm the intent.
+pub const MASS_ERASE_CONFIRM: u32 = 0xDEAD_F303;
+
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xCDEF_89AB;
+/// Maximum polls of the busy flag, longer than a page erase at 72 MHz.
+const FLASH_TIMEOUT: u32 = 4_000_000;
+
+// Raw FLASH_SR and FLASH_CR for the code running from RAM.
+const FLASH_SR: *mut u32 = <reg::flash::Sr<Srt> as Reg<Srt>>::ADDRESS as *mut u32;
+const FLASH_CR: *mut u32 = <reg::flash::Cr<Srt> as Reg<Srt>>::ADDRESS as *mut u32;
+const SR_BSY: u32 = 1 << 0;
+const SR_PGERR: u32 = 1 << 2;
+const SR_WRPRTERR: u32 = 1 << 4;
+const SR_EOP: u32 = 1 << 5;
+const CR_MER: u32 = 1 << 2;
+const CR_STRT: u32 = 1 << 6;
+// Raw SCB_AIRCR, to reset the system from RAM.
+const SCB_AIRCR: *mut u32 = 0xE000_ED0C as *mut u32;
+const AIRCR_SYSRESETREQ: u32 = 0x05FA_0000 | 1 << 2;
+
+/// Flash error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlashError {
+    /// The control register stayed locked, the key sequence was wrong.
+    Locked,
+    /// The target wasn't erased before programming (PGERR).
+    Programming,
+    /// The target is write protected (WRPRTERR).
+    WriteProtected,
+    /// The operation didn't end, or ended without EOP.
+    Timeout,
+    /// The programmed value doesn't read back.
+    Verify,
+    /// The address is misaligned or outside the flash memory.
+    Address,
+    /// The mass erase wasn't confirmed.
+    NotConfirmed,
+    /// The option bytes write enable wasn't granted, the key sequence was
+    /// wrong.
+    OptionsLocked,
+    /// The requested read protection change is refused, see
+    /// [`Flash::program_option_bytes`].
+    ReadProtection,
+    /// The wait states are too few for the SYSCLK.
+    LatencyTooLow,
+    /// The half-cycle access is on above [`HALF_CYCLE_MAX_SYSCLK`].
+    HalfCycleTooFast,
+    /// The prefetch buffer can't be switched at or above
+    /// [`PREFETCH_SWITCH_MAX_SYSCLK`].
+    PrefetchTooFast,
+    /// The AHB prescaler isn't 1, as required for the half-cycle access and
+    /// for switching the prefetch buffer.
+    AhbPrescaled,
+}
+
+/// Flash read access wait states (field FLASH_ACR LATENCY).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FlashLatency {
+    /// Zero wait states, SYSCLK up to 24 MHz.
+    Ws0 = 0b000,
+    /// One wait state, SYSCLK up to 48 MHz.
+    Ws1 = 0b001,
+    /// Two wait states, SYSCLK up to 72 MHz.
+    Ws2 = 0b010,
+}
+
+impl FlashLatency {
+    /// Returns the fewest wait states which are safe at `sysclk`.
+    pub fn for_sysclk(sysclk: u32) -> Self {
+        if sysclk <= FlashLatency::Ws0.max_sysclk() {
+            FlashLatency::Ws0
+        } else if sysclk <= FlashLatency::Ws1.max_sysclk() {
+            FlashLatency::Ws1
+        } else {
+            FlashLatency::Ws2
+        }
+    }
+
+    /// Returns the highest SYSCLK supported with these wait states.
+    pub fn max_sysclk(self) -> u32 {
+        match self {
+            FlashLatency::Ws0 => 24_000_000,
+            FlashLatency::Ws1 => 48_000_000,
+            FlashLatency::Ws2 => 72_000_000,
+        }
+    }
+
+    /// Returns the value of field LATENCY.
+    pub fn bits(self) -> u32 {
+        self as u32
+    }
+
+    /// Decodes field LATENCY.
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            0b000 => Some(FlashLatency::Ws0),
+            0b001 => Some(FlashLatency::Ws1),
+            0b010 => Some(FlashLatency::Ws2),
+            _ => None,
+        }
+    }
+}
+
+/// Read protection level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RdpLevel {
+    /// No protection.
+    Level0,
+    /// Flash can't be read by the debugger or the bootloader.
+    Level1,
+    /// Debug disabled for good. Irreversible.
+    Level2,
+}
+
+impl RdpLevel {
+    fn from_byte(byte: u8) -> Self {
+        match byte {
+            0xAA => RdpLevel::Level0,
+            0xCC => RdpLevel::Level2,
+            _ => RdpLevel::Level1,
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            RdpLevel::Level0 => 0xAA,
+            RdpLevel::Level1 => 0x00,
+            RdpLevel::Level2 => 0xCC,
+        }
+    }
+}
+
+/// User option bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UserOptions {
+    /// Watchdog started by software, not by hardware (WDG_SW).
+    pub wdg_sw: bool,
+    /// No reset when entering Stop mode (nRST_STOP).
+    pub nrst_stop: bool,
+    /// No reset when entering Standby mode (nRST_STDBY).
+    pub nrst_stdby: bool,
+    /// Boot from main flash or system memory with BOOT0 high (nBOOT1).
+    pub nboot1: bool,
+    /// Analog supply monitoring enabled (VDDA_MONITOR).
+    pub vdda_monitor: bool,
+    /// SRAM parity check enabled (inverse of SRAM_PE).
+    pub sram_parity: bool,
+}
+
+impl UserOptions {
+    fn from_byte(byte: u8) -> Self {
+        Self {
+            wdg_sw: byte & 1 << 0 != 0,
+            nrst_stop: byte & 1 << 1 != 0,
+            nrst_stdby: byte & 1 << 2 != 0,
+            nboot1: byte & 1 << 4 != 0,
+            vdda_monitor: byte & 1 << 5 != 0,
+            sram_parity: byte & 1 << 6 == 0,
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        // Reserved bits 3 and 7 stay set.
+        let mut byte = 1 << 3 | 1 << 7;
+        byte |= (self.wdg_sw as u8) << 0;
+        byte |= (self.nrst_stop as u8) << 1;
+        byte |= (self.nrst_stdby as u8) << 2;
+        byte |= (self.nboot1 as u8) << 4;
+        byte |= (self.vdda_monitor as u8) << 5;
+        byte |= (!self.sram_parity as u8) << 6;
+        byte
+    }
+}
+
+/// The option bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OptionBytes {
+    /// Read protection level.
+    pub rdp: RdpLevel,
+    /// User option bits.
+    pub user: UserOptions,
+    /// User data byte 0.
+    pub data0: u8,
+    /// User data byte 1.
+    pub data1: u8,
+    /// Write protection, bit `n` set protects pages `2n` and `2n + 1`.
+    pub wrp: u16,
+}
 
 /// Flash driver.
 pub struct Flash {
@@ -23,11 +237,323 @@ impl Flash {
 
     /// Initializes flash.
     pub fn init(&self) {
-        self.periph.flash_acr.store(|r| r.set_prftbe().write_latency(0b10));
+        self.periph
+            .flash_acr
+            .store(|r| r.set_prftbe().write_latency(FlashLatency::Ws2.bits()));
+    }
+
+    /// Set the read access latency for flash, running at `sysclk`.
+    ///
+    /// Too few wait states for `sysclk` are refused. Raise the latency before
+    /// switching to a faster clock, and lower it only afterwards.
+    pub fn set_latency(&self, latency: FlashLatency, sysclk: u32) -> Result<(), FlashError> {
+        if sysclk > latency.max_sysclk() {
+            return Err(FlashError::LatencyTooLow);
+        }
+        self.periph.flash_acr.modify(|r| r.write_latency(latency.bits()));
+        Ok(())
+    }
+
+    /// Returns the current read access latency.
+    pub fn latency(&self) -> FlashLatency {
+        let bits = self.periph.flash_acr.load().latency();
+        FlashLatency::from_bits(bits).unwrap_or(FlashLatency::Ws2)
+    }
+
+    /// Checks that the flash can be read at `sysclk` divided by `hpre` with
+    /// the current settings. Call it before switching the clocks.
+    pub fn check_clock(&self, sysclk: u32, hpre: AhbPrescaler) -> Result<(), FlashError> {
+        if sysclk > self.latency().max_sysclk() {
+            Err(FlashError::LatencyTooLow)
+        } else if self.is_half_cycle_enabled() {
+            check_half_cycle(sysclk, hpre)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Turns the prefetch buffer on or off, running at `sysclk` divided by
+    /// `hpre`. Switching it is only allowed while SYSCLK is below
+    /// [`PREFETCH_SWITCH_MAX_SYSCLK`] without AHB prescaler.
+    pub fn set_prefetch(
+        &self,
+        enable: bool,
+        sysclk: u32,
+        hpre: AhbPrescaler,
+    ) -> Result<(), FlashError> {
+        if enable == self.is_prefetch_enabled() {
+            return Ok(());
+        }
+        if sysclk >= PREFETCH_SWITCH_MAX_SYSCLK {
+            return Err(FlashError::PrefetchTooFast);
+        }
+        if hpre != AhbPrescaler::Div1 {
+            return Err(FlashError::AhbPrescaled);
+        }
+        self.periph.flash_acr.modify(|r| if enable { r.set_prftbe() } else { r.clear_prftbe() });
+        Ok(())
+    }
+
+    /// Returns `true` if the prefetch buffer is on (field PRFTBS).
+    pub fn is_prefetch_enabled(&self) -> bool {
+        self.periph.flash_acr.load().prftbs()
+    }
+
+    /// Turns the half-cycle access on or off, running at `sysclk` divided by
+    /// `hpre`. It saves power at low frequencies, and is refused above
+    /// [`HALF_CYCLE_MAX_SYSCLK`] or with an AHB prescaler.
+    pub fn set_half_cycle(
+        &self,
+        enable: bool,
+        sysclk: u32,
+        hpre: AhbPrescaler,
+    ) -> Result<(), FlashError> {
+        if enable {
+            check_half_cycle(sysclk, hpre)?;
+        }
+        self.periph.flash_acr.modify(|r| if enable { r.set_hlfcya() } else { r.clear_hlfcya() });
+        Ok(())
+    }
+
+    /// Returns `true` if the half-cycle access is on.
+    pub fn is_half_cycle_enabled(&self) -> bool {
+        self.periph.flash_acr.load().hlfcya()
+    }
+
+    /// Unlocks FLASH_CR for programming and erasing.
+    pub fn unlock(&self) -> Result<(), FlashError> {
+        if self.periph.flash_cr.load().lock() {
+            self.periph.flash_keyr.store(|r| r.write_fkeyr(KEY1));
+            self.periph.flash_keyr.store(|r| r.write_fkeyr(KEY2));
+        }
+        if self.periph.flash_cr.load().lock() {
+            Err(FlashError::Locked)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Locks FLASH_CR until the next [`Flash::unlock`].
+    pub fn lock(&self) {
+        self.periph.flash_cr.modify(|r| r.set_lock());
+    }
+
+    /// Returns `true` while an operation is ongoing.
+    pub fn is_busy(&self) -> bool {
+        self.periph.flash_sr.load().bsy()
+    }
+
+    /// Programs `value` at the half-word aligned `addr`.
+    pub fn program_half_word(&self, addr: u32, value: u16) -> Result<(), FlashError> {
+        check_range(addr, 2)?;
+        self.wait_idle()?;
+        self.clear_status();
+        self.periph.flash_cr.modify(|r| r.set_pg());
+        unsafe { ptr::write_volatile(addr as *mut u16, value) };
+        let result = wait_done();
+        self.periph.flash_cr.modify(|r| r.clear_pg());
+        result?;
+        if unsafe { ptr::read_volatile(addr as *const u16) } == value {
+            Ok(())
+        } else {
+            Err(FlashError::Verify)
+        }
+    }
+
+    /// Programs `data` starting at the half-word aligned `addr`.
+    pub fn program(&self, addr: u32, data: &[u16]) -> Result<(), FlashError> {
+        check_range(addr, data.len() as u32 * 2)?;
+        for (i, &value) in data.iter().enumerate() {
+            self.program_half_word(addr + i as u32 * 2, value)?;
+        }
+        Ok(())
+    }
+
+    /// Erases the page containing `addr`.
+    pub fn erase_page(&self, addr: u32) -> Result<(), FlashError> {
+        check_range(addr, 1)?;
+        self.wait_idle()?;
+        self.clear_status();
+        self.periph.flash_cr.modify(|r| r.set_per());
+        self.periph.flash_ar.store(|r| r.write_far(addr));
+        self.periph.flash_cr.modify(|r| r.set_strt());
+        let result = wait_done();
+        self.periph.flash_cr.modify(|r| r.clear_per());
+        result
+    }
+
+    /// Erases the whole flash memory, including this program, and resets
+    /// the system.
+    ///
+    /// `confirm` must be [`MASS_ERASE_CONFIRM`]. This only returns on errors
+    /// detected before the erase starts. Once it started, interrupts are
+    /// masked and the system is reset whatever the outcome, as there is no
+    /// code left to return to.
+    ///
+    /// # Safety
+    ///
+    /// Everything in flash is lost. The device won't boot until it's
+    /// reprogrammed, e.g. by the debugger.
+    pub unsafe fn mass_erase(&self, confirm: u32) -> Result<Infallible, FlashError> {
+        if confirm != MASS_ERASE_CONFIRM {
+            return Err(FlashError::NotConfirmed);
+        }
+        self.wait_idle()?;
+        self.clear_status();
+        mass_erase_from_ram()
+    }
+
+    /// Returns the option bytes as stored. They take effect after the next
+    /// reset or [`Flash::reload_option_bytes`].
+    pub fn option_bytes(&self) -> OptionBytes {
+        let byte = |index: u32| unsafe {
+            ptr::read_volatile((OPTION_BYTES_BASE + index * 2) as *const u16) as u8
+        };
+        OptionBytes {
+            rdp: RdpLevel::from_byte(byte(0)),
+            user: UserOptions::from_byte(byte(1)),
+            data0: byte(2),
+            data1: byte(3),
+            // Cleared bits protect.
+            wrp: !u16::from_le_bytes([byte(4), byte(5)]),
+        }
     }
 
-    /// Set the read access latency for flash.
-    pub fn set_latency(&self, latency: u32) {
-        self.periph.flash_acr.store(|r| r.set_prftbe().write_latency(latency));
+    /// Erases and programs the option bytes.
+    ///
+    /// Setting [`RdpLevel::Level2`] is refused, as it can't be undone. So is
+    /// going from level 1 back to level 0, which mass erases the flash and
+    /// this program with it.
+    pub fn program_option_bytes(&self, ob: &OptionBytes) -> Result<(), FlashError> {
+        let current = self.option_bytes().rdp;
+        if ob.rdp == RdpLevel::Level2 || current != RdpLevel::Level0 && ob.rdp == RdpLevel::Level0
+        {
+            return Err(FlashError::ReadProtection);
+        }
+        let [wrp0, wrp1] = (!ob.wrp).to_le_bytes();
+        let bytes = [ob.rdp.to_byte(), ob.user.to_byte(), ob.data0, ob.data1, wrp0, wrp1];
+        self.unlock()?;
+        let result = self.unlock_options().and_then(|()| {
+            self.wait_idle()?;
+            self.clear_status();
+            self.periph.flash_cr.modify(|r| r.set_opter());
+            self.periph.flash_cr.modify(|r| r.set_strt());
+            let erased = wait_done();
+            self.periph.flash_cr.modify(|r| r.clear_opter());
+            erased?;
+            self.periph.flash_cr.modify(|r| r.set_optpg());
+            // The complement bytes are written by the hardware.
+            let programmed = bytes.iter().enumerate().try_for_each(|(index, &byte)| {
+                let addr = OPTION_BYTES_BASE + index as u32 * 2;
+                unsafe { ptr::write_volatile(addr as *mut u16, u16::from(byte)) };
+                wait_done()
+            });
+            self.periph.flash_cr.modify(|r| r.clear_optpg());
+            programmed
+        });
+        self.periph.flash_cr.modify(|r| r.clear_optwre().set_lock());
+        result
+    }
+
+    /// Reloads the option bytes, which resets the system.
+    pub fn reload_option_bytes(&self) -> ! {
+        self.unlock().ok();
+        self.unlock_options().ok();
+        self.periph.flash_cr.modify(|r| r.set_obl_launch());
+        loop {}
+    }
+
+    fn unlock_options(&self) -> Result<(), FlashError> {
+        self.periph.flash_optkeyr.store(|r| r.write_optkeyr(KEY1));
+        self.periph.flash_optkeyr.store(|r| r.write_optkeyr(KEY2));
+        if self.periph.flash_cr.load().optwre() {
+            Ok(())
+        } else {
+            Err(FlashError::OptionsLocked)
+        }
+    }
+
+    fn wait_idle(&self) -> Result<(), FlashError> {
+        for _ in 0..FLASH_TIMEOUT {
+            if !self.is_busy() {
+                return Ok(());
+            }
+        }
+        Err(FlashError::Timeout)
+    }
+
+    fn clear_status(&self) {
+        self.periph.flash_sr.store(|r| r.set_eop().set_wrprterr().set_pgerr());
+    }
+}
+
+fn check_half_cycle(sysclk: u32, hpre: AhbPrescaler) -> Result<(), FlashError> {
+    if sysclk > HALF_CYCLE_MAX_SYSCLK {
+        Err(FlashError::HalfCycleTooFast)
+    } else if hpre != AhbPrescaler::Div1 {
+        Err(FlashError::AhbPrescaled)
+    } else {
+        Ok(())
+    }
+}
+
+fn check_range(addr: u32, len: u32) -> Result<(), FlashError> {
+    let in_flash = addr.checked_add(len).map_or(false, |end| end <= FLASH_BASE + FLASH_SIZE);
+    if addr % 2 != 0 || addr < FLASH_BASE || !in_flash {
+        Err(FlashError::Address)
+    } else {
+        Ok(())
+    }
+}
+
+/// Waits for the end of an operation and reports its status.
+///
+/// Runs from RAM, so that no flash fetch stalls while the flash is busy.
+/// Nothing in here may call into flash, hence the raw registers and
+/// intrinsics instead of the register tokens.
+#[inline(never)]
+#[link_section = ".data.flash"]
+fn wait_done() -> Result<(), FlashError> {
+    let mut polls = 0;
+    while polls < FLASH_TIMEOUT {
+        let sr = unsafe { intrinsics::volatile_load(FLASH_SR) };
+        if sr & SR_BSY != 0 {
+            polls += 1;
+            continue;
+        }
+        unsafe { intrinsics::volatile_store(FLASH_SR, SR_EOP | SR_WRPRTERR | SR_PGERR) };
+        return if sr & SR_WRPRTERR != 0 {
+            Err(FlashError::WriteProtected)
+        } else if sr & SR_PGERR != 0 {
+            Err(FlashError::Programming)
+        } else if sr & SR_EOP != 0 {
+            Ok(())
+        } else {
+            Err(FlashError::Timeout)
+        };
+    }
+    Err(FlashError::Timeout)
+}
+
+/// Runs the whole mass erase from RAM, as the flash is gone halfway, then
+/// resets the system.
+///
+/// Interrupts are masked first, their vectors are in flash.
+#[inline(never)]
+#[link_section = ".data.flash"]
+fn mass_erase_from_ram() -> ! {
+    unsafe {
+        llvm_asm!("cpsid i" :::: "volatile");
+        intrinsics::volatile_store(FLASH_CR, intrinsics::volatile_load(FLASH_CR) | CR_MER);
+        intrinsics::volatile_store(FLASH_CR, intrinsics::volatile_load(FLASH_CR) | CR_STRT);
+    }
+    // Nothing to report the outcome to.
+    let _ = wait_done();
+    unsafe {
+        intrinsics::volatile_store(FLASH_CR, intrinsics::volatile_load(FLASH_CR) & !CR_MER);
+        llvm_asm!("dsb" :::: "volatile");
+        intrinsics::volatile_store(SCB_AIRCR, AIRCR_SYSRESETREQ);
+        llvm_asm!("dsb" :::: "volatile");
     }
+    loop {}
 }
@@ -2,14 +2,44 @@
 
 use crate::drv::common::DrvRcc;
 use drone_cortexm::reg::prelude::*;
+use core::marker::PhantomData;
 use drone_stm32_map::periph::gpio::head::{GpioHeadMap, GpioHeadPeriph};
-use drone_core::{inventory, inventory::Inventory};
+use drone_core::{bitfield::Bitfield, inventory, inventory::Inventory};
 use typenum::{U0, U1};
 
 /// GPIO port head driver.
 //pub struct GpioHead<T: GpioHeadMap>(Inventory<GpioHeadEn<T>, 0>);
 pub struct GpioHead<T: GpioHeadMap>(Inventory<GpioHeadEn<T>, U0>);
 
+/// Bit LCKK of GPIOx_LCKR.
+const LCKK: u32 = 1 << 16;
+
+/// An error returned by [`GpioHeadEn::lock`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockError {
+    /// The port was locked before, it stays so until the next reset. The
+    /// locked pins are given.
+    AlreadyLocked(u16),
+    /// The key sequence didn't lock the port.
+    Failed,
+}
+
+/// Proof that the configuration of some pins of a port is locked until the
+/// next reset.
+#[derive(Debug)]
+pub struct PortLock<T: GpioHeadMap> {
+    pins: u16,
+    _port: PhantomData<T>,
+}
+
+impl<T: GpioHeadMap> PortLock<T> {
+    /// Returns the locked pins, bit `n` for pin `n`.
+    #[inline]
+    pub fn pins(&self) -> u16 {
+        self.pins
+    }
+}
+
 /// GPIO port head enabled driver.
 pub struct GpioHeadEn<T: GpioHeadMap> {
     periph: GpioHeadPeriph<T>,
@@ -53,11 +83,12 @@ impl<T: GpioHeadMap> GpioHead<T> {
     }
 
     fn setup(&self) {
+        // The port may have been left enabled by a bootloader, in which case
+        // it's taken over as it is.
         let gpioen = &self.0.periph.rcc_busenr_gpioen;
-        if gpioen.read_bit() {
-            panic!("GPIO wasn't turned off");
+        if !gpioen.read_bit() {
+            gpioen.set_bit();
         }
-        gpioen.set_bit();
     }
 }
 
@@ -78,6 +109,34 @@ impl<T: GpioHeadMap> DrvRcc for GpioHead<T> {
     }
 }
 
+impl<T: GpioHeadMap> GpioHeadEn<T> {
+    /// Locks the mode, type, speed, pull and alternate function of `pins`,
+    /// bit `n` for pin `n`, until the next reset.
+    ///
+    /// The lock can be applied only once per port, so all pins to protect
+    /// have to be passed at once.
+    pub fn lock(&self, pins: u16) -> Result<PortLock<T>, LockError> {
+        let lckr = &self.periph.gpio_lckr;
+        let current = lckr.load_val().bits();
+        if current & LCKK != 0 {
+            return Err(LockError::AlreadyLocked(current as u16));
+        }
+        // The key sequence: write 1, write 0, write 1, read, read. The pins
+        // must keep the same value throughout, so whole words are written.
+        let pins_bits = u32::from(pins);
+        let key = |lckk: u32| lckr.store_val(unsafe { Bitfield::from_bits(lckk | pins_bits) });
+        key(LCKK);
+        key(0);
+        key(LCKK);
+        lckr.load_val();
+        if lckr.load_val().bits() & LCKK != 0 {
+            Ok(PortLock { pins, _port: PhantomData })
+        } else {
+            Err(LockError::Failed)
+        }
+    }
+}
+
 impl<T: GpioHeadMap> inventory::Item for GpioHeadEn<T> {
     fn teardown(&mut self, _token: &mut inventory::GuardToken<Self>) {
         self.periph.rcc_busenr_gpioen.clear_bit()
@@ -87,6 +146,7 @@ impl<T: GpioHeadMap> inventory::Item for GpioHeadEn<T> {
 impl<T: GpioHeadMap> DrvRcc for GpioHeadEn<T> {
     fn reset(&mut self) {
         self.periph.rcc_busrstr_gpiorst.set_bit();
+        self.periph.rcc_busrstr_gpiorst.clear_bit();
     }
 
     fn disable_stop_mode(&self) {
@@ -0,0 +1,493 @@
+//! Typed GPIO pin driver.
+//!
+//! [`GpioPin`] carries the pin mode in its type, so e.g. an input can't be
+//! driven by mistake. Every mode change needs the inventory token of the
+//! enabled port the pin belongs to.
+
+use crate::drv::gpio::GpioHeadEn;
+use core::marker::PhantomData;
+use drone_core::inventory;
+use drone_cortexm::reg::prelude::*;
+use drone_stm32_map::periph::gpio::{
+    head::{GpioAHead, GpioBHead, GpioFHead, GpioHeadMap},
+    pin::*,
+};
+
+/// Maps a pin to the port head it belongs to.
+pub trait PinHead: GpioPinMap {
+    /// The port head.
+    type Head: GpioHeadMap;
+
+    /// Number of the pin within the port.
+    const NUMBER: u8;
+}
+
+macro_rules! pin_head {
+    ($head:ident, $($pin:ident: $number:expr),*) => {
+        $(
+            impl PinHead for $pin {
+                type Head = $head;
+                const NUMBER: u8 = $number;
+            }
+        )*
+    };
+}
+
+// The pins of the LQFP32 package.
+pin_head!(
+    GpioAHead, GpioA0: 0, GpioA1: 1, GpioA2: 2, GpioA3: 3, GpioA4: 4, GpioA5: 5, GpioA6: 6,
+    GpioA7: 7, GpioA8: 8, GpioA9: 9, GpioA10: 10, GpioA11: 11, GpioA12: 12, GpioA13: 13,
+    GpioA14: 14, GpioA15: 15
+);
+pin_head!(GpioBHead, GpioB0: 0, GpioB1: 1, GpioB3: 3, GpioB4: 4, GpioB5: 5, GpioB6: 6, GpioB7: 7);
+pin_head!(GpioFHead, GpioF0: 0, GpioF1: 1);
+
+/// Lists the alternate functions a pin supports.
+pub trait PinAf: PinHead {
+    /// Bit `n` is set if alternate function `n` is connected to the pin.
+    const AF_MASK: u16;
+
+    /// Returns `true` if alternate function `af` is connected to the pin.
+    #[inline]
+    fn supports_af(af: u8) -> bool {
+        af < 16 && Self::AF_MASK & 1 << af != 0
+    }
+}
+
+macro_rules! pin_af {
+    ($($pin:ident: [$($af:expr),*];)*) => {
+        $(
+            impl PinAf for $pin {
+                const AF_MASK: u16 = 0 $(| 1 << $af)*;
+            }
+        )*
+    };
+}
+
+// Alternate functions of the STM32F303K8, from the DS9866 datasheet.
+pin_af! {
+    GpioA0: [1, 3, 7, 15];
+    GpioA1: [1, 3, 7, 9, 15];
+    GpioA2: [1, 3, 7, 8, 9, 15];
+    GpioA3: [1, 3, 7, 9, 15];
+    GpioA4: [2, 3, 5, 7, 15];
+    GpioA5: [1, 3, 5, 15];
+    GpioA6: [1, 2, 3, 5, 6, 15];
+    GpioA7: [1, 2, 3, 5, 6, 15];
+    GpioA8: [0, 6, 7, 15];
+    GpioA9: [3, 6, 7, 9, 10, 15];
+    GpioA10: [1, 3, 6, 7, 8, 10, 15];
+    GpioA11: [6, 7, 9, 11, 12, 15];
+    GpioA12: [1, 6, 7, 8, 9, 11, 15];
+    GpioA13: [0, 1, 3, 5, 7, 15];
+    GpioA14: [0, 3, 4, 6, 7, 15];
+    GpioA15: [0, 1, 3, 4, 5, 7, 9, 15];
+    GpioB0: [2, 3, 6, 15];
+    GpioB1: [2, 3, 6, 8, 15];
+    GpioB3: [0, 1, 3, 5, 7, 10, 15];
+    GpioB4: [0, 1, 2, 3, 5, 7, 10, 15];
+    GpioB5: [1, 2, 4, 5, 7, 10, 15];
+    GpioB6: [1, 3, 4, 7, 15];
+    GpioB7: [1, 3, 4, 7, 10, 15];
+    GpioF0: [6];
+    GpioF1: [];
+}
+
+/// Not configured by this driver yet, as left by reset or the bootloader.
+pub struct Unset;
+/// Digital input.
+pub struct Input;
+/// Push-pull output.
+pub struct PushPullOutput;
+/// Open-drain output.
+pub struct OpenDrainOutput;
+/// Analog mode, for the ADC, DAC and comparators.
+pub struct Analog;
+/// Alternate function.
+pub struct Alternate;
+
+/// A mode in which the pin drives its output.
+pub trait OutputMode {}
+
+impl OutputMode for PushPullOutput {}
+impl OutputMode for OpenDrainOutput {}
+
+/// A mode in which the input data register reflects the pin.
+pub trait InputMode {}
+
+impl InputMode for Input {}
+impl InputMode for PushPullOutput {}
+impl InputMode for OpenDrainOutput {}
+impl InputMode for Alternate {}
+
+/// Internal pull resistor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pull {
+    /// No pull-up or pull-down.
+    None = 0b00,
+    /// Pull-up.
+    Up = 0b01,
+    /// Pull-down.
+    Down = 0b10,
+}
+
+/// Output driver type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputType {
+    /// Push-pull.
+    PushPull,
+    /// Open-drain.
+    OpenDrain,
+}
+
+/// Output slew rate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Speed {
+    /// Up to 2 MHz.
+    Low = 0b00,
+    /// Up to 10 MHz.
+    Medium = 0b01,
+    /// Up to 50 MHz.
+    High = 0b11,
+}
+
+/// Output level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    /// Low.
+    Low,
+    /// High, or released in open-drain mode.
+    High,
+}
+
+/// Settings applied by [`GpioPin::into_configured`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PinConfig {
+    /// Internal pull resistor. Ignored in analog mode.
+    pub pull: Pull,
+    /// Output slew rate.
+    pub speed: Speed,
+    /// Initial output level, set before the output is enabled.
+    pub level: Level,
+}
+
+/// A mode [`GpioPin::into_configured`] can switch to.
+pub trait PinMode {
+    #[doc(hidden)]
+    fn configure<Pin: PinHead>(periph: &GpioPinPeriph<Pin>, config: PinConfig);
+}
+
+fn configure_output<Pin: PinHead>(periph: &GpioPinPeriph<Pin>, config: PinConfig) {
+    match config.level {
+        Level::Low => periph.gpio_bsrr_br.set_bit(),
+        Level::High => periph.gpio_bsrr_bs.set_bit(),
+    }
+    periph.gpio_ospeedr_ospeedr.write_bits(config.speed as u32);
+    periph.gpio_pupdr_pupdr.write_bits(config.pull as u32);
+    periph.gpio_moder_moder.write_bits(0b01);
+}
+
+impl PinMode for PushPullOutput {
+    fn configure<Pin: PinHead>(periph: &GpioPinPeriph<Pin>, config: PinConfig) {
+        periph.gpio_otyper_ot.clear_bit();
+        configure_output(periph, config);
+    }
+}
+
+impl PinMode for OpenDrainOutput {
+    fn configure<Pin: PinHead>(periph: &GpioPinPeriph<Pin>, config: PinConfig) {
+        periph.gpio_otyper_ot.set_bit();
+        configure_output(periph, config);
+    }
+}
+
+impl PinMode for Input {
+    fn configure<Pin: PinHead>(periph: &GpioPinPeriph<Pin>, config: PinConfig) {
+        periph.gpio_moder_moder.write_bits(0b00);
+        periph.gpio_pupdr_pupdr.write_bits(config.pull as u32);
+    }
+}
+
+impl PinMode for Analog {
+    fn configure<Pin: PinHead>(periph: &GpioPinPeriph<Pin>, _config: PinConfig) {
+        periph.gpio_pupdr_pupdr.write_bits(Pull::None as u32);
+        periph.gpio_moder_moder.write_bits(0b11);
+    }
+}
+
+/// Electrical settings of an alternate function pin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AfConfig {
+    /// Output driver type.
+    pub output: OutputType,
+    /// Output slew rate.
+    pub speed: Speed,
+    /// Internal pull resistor.
+    pub pull: Pull,
+}
+
+impl Default for AfConfig {
+    fn default() -> Self {
+        Self { output: OutputType::PushPull, speed: Speed::Low, pull: Pull::None }
+    }
+}
+
+/// An error returned when a pin doesn't support the requested alternate
+/// function. The pin is handed back unchanged.
+pub struct InvalidAf<T> {
+    /// The pin.
+    pub pin: T,
+    /// The rejected alternate function.
+    pub af: u8,
+}
+
+impl<T> core::fmt::Debug for InvalidAf<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "InvalidAf({})", self.af)
+    }
+}
+
+/// Head enable token of the port of `Pin`.
+pub type HeadToken<'a, Pin> = &'a inventory::Token<GpioHeadEn<<Pin as PinHead>::Head>>;
+
+/// Gives the head enable token of port `Head`. Implemented for single tokens
+/// and for tuples of tokens of different ports, so pins of several ports can
+/// be set up at once.
+pub trait HeadTokens<Head: GpioHeadMap> {
+    /// Returns the token of port `Head`.
+    fn head_token(&self) -> &inventory::Token<GpioHeadEn<Head>>;
+}
+
+impl<Head: GpioHeadMap> HeadTokens<Head> for inventory::Token<GpioHeadEn<Head>> {
+    #[inline]
+    fn head_token(&self) -> &inventory::Token<GpioHeadEn<Head>> {
+        self
+    }
+}
+
+macro_rules! head_tokens {
+    ($(($($head:ident),*).$index:tt => $target:ident;)*) => {
+        $(
+            impl HeadTokens<$target> for ($(&inventory::Token<GpioHeadEn<$head>>,)*) {
+                #[inline]
+                fn head_token(&self) -> &inventory::Token<GpioHeadEn<$target>> {
+                    self.$index
+                }
+            }
+        )*
+    };
+}
+
+head_tokens! {
+    (GpioAHead, GpioBHead).0 => GpioAHead;
+    (GpioAHead, GpioBHead).1 => GpioBHead;
+    (GpioAHead, GpioFHead).0 => GpioAHead;
+    (GpioAHead, GpioFHead).1 => GpioFHead;
+    (GpioBHead, GpioFHead).0 => GpioBHead;
+    (GpioBHead, GpioFHead).1 => GpioFHead;
+    (GpioAHead, GpioBHead, GpioFHead).0 => GpioAHead;
+    (GpioAHead, GpioBHead, GpioFHead).1 => GpioBHead;
+    (GpioAHead, GpioBHead, GpioFHead).2 => GpioFHead;
+}
+
+/// Acquires the peripheral of pin `$pin`, e.g. `gpio_pin_periph!(reg, GpioB4)`.
+#[macro_export]
+macro_rules! gpio_pin_periph {
+    ($reg:ident, GpioA0) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_a0) };
+    ($reg:ident, GpioA1) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_a1) };
+    ($reg:ident, GpioA2) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_a2) };
+    ($reg:ident, GpioA3) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_a3) };
+    ($reg:ident, GpioA4) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_a4) };
+    ($reg:ident, GpioA5) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_a5) };
+    ($reg:ident, GpioA6) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_a6) };
+    ($reg:ident, GpioA7) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_a7) };
+    ($reg:ident, GpioA8) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_a8) };
+    ($reg:ident, GpioA9) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_a9) };
+    ($reg:ident, GpioA10) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_a10) };
+    ($reg:ident, GpioA11) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_a11) };
+    ($reg:ident, GpioA12) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_a12) };
+    ($reg:ident, GpioA13) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_a13) };
+    ($reg:ident, GpioA14) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_a14) };
+    ($reg:ident, GpioA15) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_a15) };
+    ($reg:ident, GpioB0) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_b0) };
+    ($reg:ident, GpioB1) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_b1) };
+    ($reg:ident, GpioB3) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_b3) };
+    ($reg:ident, GpioB4) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_b4) };
+    ($reg:ident, GpioB5) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_b5) };
+    ($reg:ident, GpioB6) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_b6) };
+    ($reg:ident, GpioB7) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_b7) };
+    ($reg:ident, GpioF0) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_f0) };
+    ($reg:ident, GpioF1) => { $crate::gpio_pin_periph!(@periph $reg, periph_gpio_f1) };
+    (@periph $reg:ident, $periph:ident) => {
+        ::drone_stm32_map::periph::gpio::$periph!($reg)
+    };
+}
+
+/// GPIO pin driver.
+pub struct GpioPin<Pin: PinHead, Mode> {
+    periph: GpioPinPeriph<Pin>,
+    _mode: PhantomData<Mode>,
+}
+
+impl<Pin: PinHead> GpioPin<Pin, Unset> {
+    /// Creates a new [`GpioPin`].
+    #[inline]
+    pub fn new(periph: GpioPinPeriph<Pin>) -> Self {
+        Self { periph, _mode: PhantomData }
+    }
+}
+
+impl<Pin: PinHead, Mode> GpioPin<Pin, Mode> {
+    /// Releases the peripheral, leaving the pin as it is.
+    #[inline]
+    pub fn free(self) -> GpioPinPeriph<Pin> {
+        self.periph
+    }
+
+    /// Turns the pin into a push-pull output, initially low.
+    pub fn into_push_pull_output(
+        self,
+        _token: HeadToken<'_, Pin>,
+    ) -> GpioPin<Pin, PushPullOutput> {
+        self.periph.gpio_bsrr_br.set_bit();
+        self.periph.gpio_otyper_ot.clear_bit();
+        self.periph.gpio_pupdr_pupdr.write_bits(Pull::None as u32);
+        self.periph.gpio_moder_moder.write_bits(0b01);
+        self.into_mode()
+    }
+
+    /// Turns the pin into an open-drain output, initially released.
+    pub fn into_open_drain_output(
+        self,
+        _token: HeadToken<'_, Pin>,
+    ) -> GpioPin<Pin, OpenDrainOutput> {
+        self.periph.gpio_bsrr_bs.set_bit();
+        self.periph.gpio_otyper_ot.set_bit();
+        self.periph.gpio_moder_moder.write_bits(0b01);
+        self.into_mode()
+    }
+
+    /// Turns the pin into an input with the given `pull`.
+    pub fn into_input(self, _token: HeadToken<'_, Pin>, pull: Pull) -> GpioPin<Pin, Input> {
+        self.periph.gpio_moder_moder.write_bits(0b00);
+        self.periph.gpio_pupdr_pupdr.write_bits(pull as u32);
+        self.into_mode()
+    }
+
+    /// Turns the pin into analog mode.
+    pub fn into_analog(self, _token: HeadToken<'_, Pin>) -> GpioPin<Pin, Analog> {
+        self.periph.gpio_pupdr_pupdr.write_bits(Pull::None as u32);
+        self.periph.gpio_moder_moder.write_bits(0b11);
+        self.into_mode()
+    }
+
+    /// Switches the pin to `New` mode with the given `config`.
+    pub fn into_configured<New: PinMode>(
+        self,
+        _token: HeadToken<'_, Pin>,
+        config: PinConfig,
+    ) -> GpioPin<Pin, New> {
+        New::configure(&self.periph, config);
+        self.into_mode()
+    }
+
+    /// Returns the bit of the pin in the port registers, e.g. for
+    /// [`GpioHeadEn::lock`].
+    #[inline]
+    pub fn mask(&self) -> u16 {
+        1 << Pin::NUMBER
+    }
+
+    /// Sets the output slew rate.
+    #[inline]
+    pub fn set_speed(&self, speed: Speed) {
+        self.periph.gpio_ospeedr_ospeedr.write_bits(speed as u32);
+    }
+
+    /// Sets the internal pull resistor.
+    #[inline]
+    pub fn set_pull(&self, pull: Pull) {
+        self.periph.gpio_pupdr_pupdr.write_bits(pull as u32);
+    }
+
+    fn into_mode<New>(self) -> GpioPin<Pin, New> {
+        GpioPin { periph: self.periph, _mode: PhantomData }
+    }
+}
+
+impl<Pin: PinAf, Mode> GpioPin<Pin, Mode> {
+    /// Connects the pin to alternate function `af`, 0 to 15, with the given
+    /// electrical `config`.
+    ///
+    /// Functions which aren't connected to the pin, see [`PinAf`], are
+    /// rejected.
+    pub fn into_alternate(
+        self,
+        _token: HeadToken<'_, Pin>,
+        af: u8,
+        config: AfConfig,
+    ) -> Result<GpioPin<Pin, Alternate>, InvalidAf<Self>> {
+        if !Pin::supports_af(af) {
+            return Err(InvalidAf { pin: self, af });
+        }
+        match config.output {
+            OutputType::PushPull => self.periph.gpio_otyper_ot.clear_bit(),
+            OutputType::OpenDrain => self.periph.gpio_otyper_ot.set_bit(),
+        }
+        self.set_speed(config.speed);
+        self.set_pull(config.pull);
+        self.periph.gpio_afr_afr.write_bits(u32::from(af));
+        self.periph.gpio_moder_moder.write_bits(0b10);
+        Ok(self.into_mode())
+    }
+}
+
+impl<Pin: PinHead, Mode: OutputMode> GpioPin<Pin, Mode> {
+    /// Drives the pin high, or releases it in open-drain mode.
+    #[inline]
+    pub fn set_high(&self) {
+        self.periph.gpio_bsrr_bs.set_bit();
+    }
+
+    /// Drives the pin low.
+    #[inline]
+    pub fn set_low(&self) {
+        self.periph.gpio_bsrr_br.set_bit();
+    }
+
+    /// Drives the pin high if `high`, low otherwise.
+    #[inline]
+    pub fn set(&self, high: bool) {
+        if high {
+            self.set_high();
+        } else {
+            self.set_low();
+        }
+    }
+
+    /// Inverts the output.
+    #[inline]
+    pub fn toggle(&self) {
+        self.set(!self.is_set_high());
+    }
+
+    /// Returns `true` if the output is set high.
+    #[inline]
+    pub fn is_set_high(&self) -> bool {
+        self.periph.gpio_odr_odr.read_bit()
+    }
+}
+
+impl<Pin: PinHead, Mode: InputMode> GpioPin<Pin, Mode> {
+    /// Returns `true` if the pin level is high.
+    #[inline]
+    pub fn is_high(&self) -> bool {
+        self.periph.gpio_idr_idr.read_bit()
+    }
+
+    /// Returns `true` if the pin level is low.
+    #[inline]
+    pub fn is_low(&self) -> bool {
+        !self.is_high()
+    }
+}
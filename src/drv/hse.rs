@@ -0,0 +1,71 @@
+//! High Speed External clock.
+//!
+//! On the NUCLEO-F303K8 no crystal is fitted. The 8 MHz MCO output of the
+//! ST-LINK can be fed into OSC_IN (PF0) instead, which requires SB4 ON and
+//! SB6 OFF, and the HSE to run in bypass mode.
+
+use crate::drv::rcc_ready::{poll_ready, ClockError, ReadySource, HSE_TIMEOUT};
+use crate::periph::hse::HsePeriph;
+use crate::sys::clock_config::HseMode;
+use crate::tasks::root::SystemRes;
+use drone_cortexm::reg::prelude::*;
+
+/// HSE driver.
+pub struct Hse {
+    periph: HsePeriph,
+}
+
+impl Hse {
+    /// Creates a new [`Hse`].
+    #[inline]
+    pub fn new(periph: HsePeriph) -> Self {
+        Self { periph }
+    }
+
+    /// Releases the peripheral.
+    #[inline]
+    pub fn free(self) -> HsePeriph {
+        self.periph
+    }
+
+    /// Initializes HSE in the given `mode`, waiting for it to settle on the
+    /// `rcc` thread.
+    pub async fn init(&self, res: &SystemRes, mode: HseMode) -> Result<(), ClockError> {
+        println!("HSE init");
+        res.rcc_ready
+            .wait(
+                res,
+                ReadySource::Hse,
+                HSE_TIMEOUT,
+                ClockError::HseTimeout,
+                || {
+                    // HSEBYP can only be written while the oscillator is off.
+                    if !self.periph.rcc_cr_hseon.read_bit() {
+                        match mode {
+                            HseMode::Oscillator => self.periph.rcc_cr_hsebyp.clear_bit(),
+                            HseMode::Bypass => self.periph.rcc_cr_hsebyp.set_bit(),
+                        }
+                    }
+                    self.periph.rcc_cr_hseon.set_bit();
+                },
+                || self.periph.rcc_cr_hserdy.read_bit_band(),
+            )
+            .await
+    }
+
+    /// Disables HSE. `hclk` is the current HCLK, which times the wait.
+    pub fn disable(&self, hclk: u32) -> Result<(), ClockError> {
+        self.periph.rcc_cr_hseon.clear_bit();
+        poll_ready(HSE_TIMEOUT, hclk, ClockError::HseTimeout, || {
+            !self.periph.rcc_cr_hserdy.read_bit_band()
+        })?;
+        self.periph.rcc_cr_hsebyp.clear_bit();
+        Ok(())
+    }
+
+    /// Returns `true` if the HSE is stable.
+    #[inline]
+    pub fn is_ready(&self) -> bool {
+        self.periph.rcc_cr_hserdy.read_bit()
+    }
+}
@@ -1,9 +1,15 @@
 //! 8MHz internal RC oscillator clock.
 
+use crate::drv::rcc_ready::{ClockError, ReadySource, HSI_TIMEOUT};
 use crate::periph::hsi::HsiPeriph;
 use crate::tasks::root::SystemRes;
 use drone_cortexm::reg::prelude::*;
 
+/// Reset value of field HSITRIM.
+pub const HSI_TRIM_DEFAULT: u32 = 16;
+/// Largest value of field HSITRIM.
+pub const HSI_TRIM_MAX: u32 = 31;
+
 /// HSI driver.
 pub struct Hsi {
     periph: HsiPeriph,
@@ -22,15 +28,42 @@ impl Hsi {
         self.periph
     }
 
-    /// Initializes HSI.
-    pub fn init(&self, _res: &SystemRes) {
+    /// Initializes HSI, waiting for it to settle on the `rcc` thread.
+    pub async fn init(&self, res: &SystemRes) -> Result<(), ClockError> {
         println!("HSI init");
-        self.periph.rcc_cr_hsion.modify(|r| {
-            self.periph.rcc_cr_hsion.set(r);
-        });
-        while !self.periph.rcc_cr_hsirdy.read_bit_band() {}
+        res.rcc_ready
+            .wait(
+                res,
+                ReadySource::Hsi,
+                HSI_TIMEOUT,
+                ClockError::HsiTimeout,
+                || self.periph.rcc_cr_hsion.set_bit(),
+                || self.periph.rcc_cr_hsirdy.read_bit_band(),
+            )
+            .await
     }
 
     /// Reset the HSI configuration to default
-    pub fn reset(&self) {}
+    pub fn reset(&self) {
+        self.set_trim(HSI_TRIM_DEFAULT);
+    }
+
+    /// Returns the user trimming value (field HSITRIM).
+    #[inline]
+    pub fn trim(&self) -> u32 {
+        self.periph.rcc_cr_hsitrim.read_bits() as u32
+    }
+
+    /// Sets the user trimming value (field HSITRIM), in steps of about
+    /// 40 kHz. Values above 31 are clamped.
+    #[inline]
+    pub fn set_trim(&self, trim: u32) {
+        self.periph.rcc_cr_hsitrim.write_bits(trim.min(HSI_TRIM_MAX));
+    }
+
+    /// Returns the factory calibration value (field HSICAL).
+    #[inline]
+    pub fn cal(&self) -> u32 {
+        self.periph.rcc_cr_hsical.read_bits() as u32
+    }
 }
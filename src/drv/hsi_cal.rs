@@ -0,0 +1,174 @@
+//! HSI calibration against the LSE.
+//!
+//! The LSE is routed through the MCO to TIM16 channel 1, which captures
+//! every 8th LSE edge. The HSI is measured by counting timer clocks between
+//! two captures, for every HSITRIM value, and the best one is kept.
+//!
+//! SYSCLK must be derived from HSI while calibrating. The MCO is taken over,
+//! so the PA8 output shows the LSE meanwhile, and SWO output is garbled.
+//!
+//! The calibration needs a 32.768 kHz crystal on OSC32_IN/OSC32_OUT, which
+//! the NUCLEO-F303K8 doesn't have fitted. The LSE must be started and ready
+//! before calling [`HsiCal::calibrate`].
+
+use crate::consts::{HSI_CLK, LSE_CLK};
+use crate::drv::hsi::HSI_TRIM_MAX;
+use crate::drv::mco::{Mco, McoPrescaler, McoSource};
+use crate::drv::rcc_ready::poll_ready;
+use crate::periph::hsi_cal::HsiCalPeriph;
+use crate::sys::clock_config::{PllSrc, SysClkSrc};
+use crate::sys::system::System;
+use crate::tasks::root::SystemRes;
+use drone_cortexm::reg::prelude::*;
+
+/// LSE edges per capture.
+const EDGES_PER_CAPTURE: u32 = 8;
+/// Measurements averaged per trimming value.
+const SAMPLES: u32 = 4;
+/// Time given to one capture in milliseconds, several capture periods.
+const CAPTURE_TIMEOUT: u32 = 2;
+
+/// An error returned by [`HsiCal::calibrate`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HsiCalError {
+    /// The LSE isn't ready.
+    LseNotReady,
+    /// SYSCLK isn't derived from HSI.
+    NotOnHsi,
+    /// The timer didn't capture the LSE.
+    NoCapture,
+}
+
+/// The outcome of a calibration.
+#[derive(Clone, Copy, Debug)]
+pub struct HsiCalibration {
+    /// The chosen HSITRIM value.
+    pub trim: u32,
+    /// The measured HSI frequency at `trim`.
+    pub hsi_freq: u32,
+}
+
+/// HSI calibration driver.
+pub struct HsiCal {
+    periph: HsiCalPeriph,
+}
+
+impl HsiCal {
+    /// Creates a new [`HsiCal`].
+    #[inline]
+    pub fn new(periph: HsiCalPeriph) -> Self {
+        Self { periph }
+    }
+
+    /// Releases the peripheral.
+    #[inline]
+    pub fn free(self) -> HsiCalPeriph {
+        self.periph
+    }
+
+    /// Trims the HSI as close to 8 MHz as possible.
+    ///
+    /// The LSE must already be ready. The MCO is disabled afterwards.
+    pub fn calibrate(&self, res: &SystemRes, mco: &Mco) -> Result<HsiCalibration, HsiCalError> {
+        let on_hsi = match SysClkSrc::from_bits(res.rcc.read_sws()) {
+            Some(SysClkSrc::Hsi) => true,
+            Some(SysClkSrc::Pll) => {
+                PllSrc::from_bits(res.pll.read_pllsrc()) == Some(PllSrc::HsiDiv2)
+            }
+            _ => false,
+        };
+        if !on_hsi {
+            return Err(HsiCalError::NotOnHsi);
+        }
+        if !res.lse.is_ready() {
+            return Err(HsiCalError::LseNotReady);
+        }
+        let hclk = System::calculate_hclk(res);
+        // Nominal timer clock, as if the HSI ran at exactly 8 MHz.
+        let tim_clk = System::clock_frequencies(res).tim_apb2;
+        mco.enable(McoSource::Lse, McoPrescaler::Div1);
+        self.start();
+        let mut best: Option<(u32, u32)> = None;
+        let result = (0..=HSI_TRIM_MAX).try_for_each(|trim| {
+            res.hsi.set_trim(trim);
+            let ticks = self.measure(hclk)?;
+            // Timer ticks per capture, scaled back to the HSI frequency.
+            let hsi_freq = (u64::from(ticks) * u64::from(LSE_CLK) * u64::from(HSI_CLK)
+                / (u64::from(EDGES_PER_CAPTURE * SAMPLES) * u64::from(tim_clk)))
+                as u32;
+            let error = |freq: u32| (freq as i64 - HSI_CLK as i64).abs();
+            if best.map_or(true, |(_, freq)| error(hsi_freq) < error(freq)) {
+                best = Some((trim, hsi_freq));
+            }
+            Ok(())
+        });
+        self.stop();
+        mco.disable();
+        match (result, best) {
+            (Ok(()), Some((trim, hsi_freq))) => {
+                res.hsi.set_trim(trim);
+                Ok(HsiCalibration { trim, hsi_freq })
+            }
+            (Err(err), _) => {
+                res.hsi.reset();
+                Err(err)
+            }
+            (Ok(()), None) => {
+                res.hsi.reset();
+                Err(HsiCalError::NoCapture)
+            }
+        }
+    }
+
+    fn start(&self) {
+        let p = &self.periph;
+        p.rcc_apb2enr_tim16en.set_bit();
+        // TI1 is the MCO.
+        p.tim16_or_ti1_rmp.write_bits(0b11);
+        p.tim16_psc_psc.write_bits(0);
+        p.tim16_arr_arr.write_bits(0xFFFF);
+        p.tim16_ccmr1_input_cc1s.modify(|r| {
+            p.tim16_ccmr1_input_cc1s.write(r, 0b01); // IC1 on TI1
+            p.tim16_ccmr1_input_ic1psc.write(r, 0b11); // Every 8 events
+            p.tim16_ccmr1_input_ic1f.write(r, 0b0000);
+        });
+        p.tim16_ccer_cc1e.modify(|r| {
+            p.tim16_ccer_cc1p.clear(r); // Rising edge
+            p.tim16_ccer_cc1e.set(r);
+        });
+        p.tim16_cr1_cen.set_bit();
+    }
+
+    fn stop(&self) {
+        let p = &self.periph;
+        p.tim16_cr1_cen.clear_bit();
+        p.tim16_ccer_cc1e.clear_bit();
+        p.tim16_or_ti1_rmp.write_bits(0b00);
+        p.rcc_apb2enr_tim16en.clear_bit();
+    }
+
+    /// Returns the timer ticks of `SAMPLES` consecutive captures.
+    fn measure(&self, hclk: u32) -> Result<u32, HsiCalError> {
+        // Let the HSI settle on the new trim, and drop a stale capture.
+        self.capture(hclk)?;
+        let first = self.capture(hclk)?;
+        let mut last = first;
+        let mut ticks = 0;
+        for _ in 0..SAMPLES {
+            let next = self.capture(hclk)?;
+            ticks += u32::from(next.wrapping_sub(last));
+            last = next;
+        }
+        Ok(ticks)
+    }
+
+    fn capture(&self, hclk: u32) -> Result<u16, HsiCalError> {
+        let p = &self.periph;
+        poll_ready(CAPTURE_TIMEOUT, hclk, HsiCalError::NoCapture, || {
+            p.tim16_sr_cc1if.read_bit()
+        })?;
+        p.tim16_sr_cc1of.clear_bit();
+        // Reading CCR1 clears CC1IF.
+        Ok(p.tim16_ccr1_ccr1.read_bits() as u16)
+    }
+}
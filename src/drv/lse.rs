@@ -1,5 +1,6 @@
 //! 32.768 kHz Low Speed External resonator.
 
+use crate::drv::rcc_ready::{ClockError, ReadySource, LSE_TIMEOUT};
 use crate::periph::lse::LsePeriph;
 use crate::tasks::root::SystemRes;
 use drone_cortexm::reg::prelude::*;
@@ -22,17 +23,33 @@ impl Lse {
         self.periph
     }
 
-    /// Initializes LSE.
-    pub fn init(&self, res: &SystemRes) {
-        res.rcc.set_apb1enr_pwren();
-        res.rcc.set_pwr_cr_dbp();
-        self.periph.rcc_bdcr_lseon.modify(|r| {
-            self.periph.rcc_bdcr_lseon.set(r);
-            self.periph.rcc_bdcr_lsebyp.clear(r);
-            self.periph.rcc_bdcr_lsedrv.write(r, 0b01);
-        });
-        res.rcc.clear_apb1enr_pwren();
-        while !self.periph.rcc_bdcr_lserdy.read_bit_band() {}
+    /// Initializes LSE, waiting for it to settle on the `rcc` thread.
+    pub async fn init(&self, res: &SystemRes) -> Result<(), ClockError> {
+        res.rcc_ready
+            .wait(
+                res,
+                ReadySource::Lse,
+                LSE_TIMEOUT,
+                ClockError::LseTimeout,
+                || {
+                    res.rcc.set_apb1enr_pwren();
+                    res.rcc.set_pwr_cr_dbp();
+                    self.periph.rcc_bdcr_lseon.modify(|r| {
+                        self.periph.rcc_bdcr_lseon.set(r);
+                        self.periph.rcc_bdcr_lsebyp.clear(r);
+                        self.periph.rcc_bdcr_lsedrv.write(r, 0b01);
+                    });
+                    res.rcc.clear_apb1enr_pwren();
+                },
+                || self.periph.rcc_bdcr_lserdy.read_bit_band(),
+            )
+            .await
+    }
+
+    /// Returns `true` if the LSE is stable.
+    #[inline]
+    pub fn is_ready(&self) -> bool {
+        self.periph.rcc_bdcr_lserdy.read_bit_band()
     }
 
     pub fn reset(&self) {
@@ -0,0 +1,133 @@
+//! Microcontroller clock output.
+//!
+//! Routes one of the internal clocks to PA8 (Arduino D9), to check the clock
+//! tree with a scope or a frequency counter. GPIO port A must be enabled
+//! before [`Mco::init`].
+
+use crate::drv::gpio::GpioHeadEn;
+use crate::periph::mco::McoPeriph;
+use drone_core::inventory;
+use drone_cortexm::reg::prelude::*;
+use drone_stm32_map::periph::gpio::{
+    head::GpioAHead,
+    pin::{GpioA8, GpioPinPeriph},
+};
+
+/// Clock routed to the MCO pin (field RCC_CFGR MCO).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum McoSource {
+    /// Low speed internal RC.
+    Lsi,
+    /// Low speed external resonator.
+    Lse,
+    /// System clock.
+    Sysclk,
+    /// High speed internal RC.
+    Hsi,
+    /// High speed external clock.
+    Hse,
+    /// PLL output divided by 2.
+    PllDiv2,
+}
+
+impl McoSource {
+    /// Returns the value of field MCO.
+    pub fn bits(self) -> u32 {
+        match self {
+            McoSource::Lsi => 0b010,
+            McoSource::Lse => 0b011,
+            McoSource::Sysclk => 0b100,
+            McoSource::Hsi => 0b101,
+            McoSource::Hse => 0b110,
+            McoSource::PllDiv2 => 0b111,
+        }
+    }
+}
+
+/// Division factor of the MCO clock (field RCC_CFGR MCOPRE).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum McoPrescaler {
+    /// Clock not divided.
+    Div1 = 0b000,
+    /// Clock divided by 2.
+    Div2 = 0b001,
+    /// Clock divided by 4.
+    Div4 = 0b010,
+    /// Clock divided by 8.
+    Div8 = 0b011,
+    /// Clock divided by 16.
+    Div16 = 0b100,
+    /// Clock divided by 32.
+    Div32 = 0b101,
+    /// Clock divided by 64.
+    Div64 = 0b110,
+    /// Clock divided by 128.
+    Div128 = 0b111,
+}
+
+impl McoPrescaler {
+    /// Returns the value of field MCOPRE.
+    pub fn bits(self) -> u32 {
+        self as u32
+    }
+
+    /// Returns the division factor.
+    pub fn divisor(self) -> u32 {
+        1 << self.bits()
+    }
+}
+
+/// MCO driver.
+pub struct Mco {
+    periph: McoPeriph,
+    pin: GpioPinPeriph<GpioA8>,
+}
+
+impl Mco {
+    /// Creates a new [`Mco`].
+    #[inline]
+    pub fn new(periph: McoPeriph, pin: GpioPinPeriph<GpioA8>) -> Self {
+        Self { periph, pin }
+    }
+
+    /// Releases the peripherals.
+    #[inline]
+    pub fn free(self) -> (McoPeriph, GpioPinPeriph<GpioA8>) {
+        (self.periph, self.pin)
+    }
+
+    /// Sets PA8 to alternate function 0 (MCO), high speed.
+    pub fn init(&self, _gpio_a_en: &inventory::Token<GpioHeadEn<GpioAHead>>) {
+        self.pin.gpio_afr_afr.modify(|r| {
+            self.pin.gpio_afr_afr.write(r, 0);
+        });
+        self.pin.gpio_otyper_ot.modify(|r| {
+            self.pin.gpio_otyper_ot.clear(r);
+        });
+        self.pin.gpio_ospeedr_ospeedr.modify(|r| {
+            self.pin.gpio_ospeedr_ospeedr.write(r, 0b11);
+        });
+        self.pin.gpio_pupdr_pupdr.modify(|r| {
+            self.pin.gpio_pupdr_pupdr.write(r, 0b00);
+        });
+        self.pin.gpio_moder_moder.modify(|r| {
+            self.pin.gpio_moder_moder.write(r, 0b10); // Alternate function
+        });
+    }
+
+    /// Outputs `source` divided by `prescaler`.
+    ///
+    /// The GPIO toggles at most 72 MHz, so choose a prescaler accordingly.
+    pub fn enable(&self, source: McoSource, prescaler: McoPrescaler) {
+        self.periph.rcc_cfgr_mco.modify(|r| {
+            self.periph.rcc_cfgr_pllnodiv.clear(r);
+            self.periph.rcc_cfgr_mcopre.write(r, prescaler.bits());
+            self.periph.rcc_cfgr_mco.write(r, source.bits());
+        });
+    }
+
+    /// Stops the clock output.
+    pub fn disable(&self) {
+        self.periph.rcc_cfgr_mco.write_bits(0b000);
+    }
+}
@@ -1,11 +1,19 @@
 //! Peripheral devices.
 
+pub mod backup;
 pub mod common;
+pub mod css;
 pub mod exti;
 pub mod exti_diverged;
 pub mod flash;
 pub mod gpio;
+pub mod gpio_pin;
+pub mod hse;
 pub mod hsi;
+pub mod hsi_cal;
 pub mod lse;
+pub mod mco;
 pub mod pll;
 pub mod rcc;
+pub mod rcc_ready;
+pub mod rtc;
@@ -1,6 +1,8 @@
 //! Phase-Locked Loop clock.
 
 use crate::periph::pll::PllPeriph;
+use crate::drv::rcc_ready::{poll_ready, ClockError, ReadySource, PLL_TIMEOUT};
+use crate::sys::clock_config::ClockConfig;
 use crate::tasks::root::SystemRes;
 use drone_cortexm::reg::prelude::*;
 
@@ -23,23 +25,38 @@ impl Pll {
     }
 
     /// Initializes PLL.
-    pub fn init(&self, res: &SystemRes) {
-        self.periph.rcc_cfgr_pllsrc.write_bits(res.pllsrc);
-        self.periph.rcc_cfgr_pllmul.write_bits(res.pllmul);
+    pub fn init(&self, config: &ClockConfig) {
+        self.periph.rcc_cfgr_pllsrc.write_bits(config.pll_src().bits());
+        self.periph.rcc_cfgr_pllmul.write_bits(config.pll_mul().bits());
+        self.periph.rcc_cfgr2_prediv.write_bits(config.prediv().bits());
+        self.periph.rcc_cfgr2_adc12pres.write_bits(config.adc12_prescaler().bits());
     }
 
-    /// Enables PLL.
-    pub fn enable(&self) {
+    /// Enables PLL, waiting for the lock on the `rcc` thread.
+    pub async fn enable(&self, res: &SystemRes) -> Result<(), ClockError> {
         println!("Enable PLL");
-        self.periph.rcc_cr_pllon.set_bit();
-        while !self.periph.rcc_cr_pllrdy.read_bit() {}
+        res.rcc_ready
+            .wait(
+                res,
+                ReadySource::Pll,
+                PLL_TIMEOUT,
+                ClockError::PllTimeout,
+                || self.periph.rcc_cr_pllon.set_bit(),
+                || self.periph.rcc_cr_pllrdy.read_bit(),
+            )
+            .await?;
         println!("PLL is enabled");
+        Ok(())
     }
 
-    /// Disable PLL.
-    pub fn disable(&self) {
+    /// Disable PLL. `hclk` is the current HCLK, which times the wait.
+    ///
+    /// There is no interrupt for the PLL unlock, so this one keeps spinning.
+    pub fn disable(&self, hclk: u32) -> Result<(), ClockError> {
         self.periph.rcc_cr_pllon.clear_bit();
-        while self.periph.rcc_cr_pllrdy.read_bit() {}
+        poll_ready(PLL_TIMEOUT, hclk, ClockError::PllTimeout, || {
+            !self.periph.rcc_cr_pllrdy.read_bit()
+        })
     }
 
     /// Resets the PLL configuration to default.
@@ -47,6 +64,8 @@ impl Pll {
     pub fn reset(&self) {
         self.periph.rcc_cfgr_pllsrc.write_bits(0b00);
         self.periph.rcc_cfgr_pllmul.write_bits(0b0000);
+        self.periph.rcc_cfgr2_prediv.write_bits(0b0000);
+        self.periph.rcc_cfgr2_adc12pres.write_bits(0b00000);
     }
 
     /// Returns value of field PLLSRC.
@@ -61,4 +80,15 @@ impl Pll {
         self.periph.rcc_cfgr_pllmul.read_bits() as u32
     }
 
+    /// Returns value of field PREDIV.
+    #[inline]
+    pub fn read_prediv(&self) -> u32 {
+        self.periph.rcc_cfgr2_prediv.read_bits() as u32
+    }
+
+    /// Returns value of field ADC12PRES.
+    #[inline]
+    pub fn read_adc12pres(&self) -> u32 {
+        self.periph.rcc_cfgr2_adc12pres.read_bits() as u32
+    }
 }
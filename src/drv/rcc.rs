@@ -1,7 +1,7 @@
 //! Reset and Clock Control.
 
 use crate::periph::rcc::RccPeriph;
-use crate::tasks::root::SystemRes;
+use crate::sys::clock_config::ClockConfig;
 use drone_cortexm::reg::prelude::*;
 
 /// RCC driver.
@@ -24,9 +24,11 @@ impl Rcc {
 
     /// Initializes RCC.
     #[inline]
-    pub fn init(&self, res: &SystemRes) {
-        self.periph.rcc_cfgr_sw.write_bits(res.clksrc);
-        self.periph.rcc_cfgr_hpre.write_bits(res.hpre);
+    pub fn init(&self, config: &ClockConfig) {
+        self.periph.rcc_cfgr_hpre.write_bits(config.hpre().bits());
+        self.periph.rcc_cfgr_ppre1.write_bits(config.ppre1().bits());
+        self.periph.rcc_cfgr_ppre2.write_bits(config.ppre2().bits());
+        self.periph.rcc_cfgr_sw.write_bits(config.sysclk_src().bits());
     }
 
     /// Reset RCC to default.
@@ -42,6 +44,21 @@ impl Rcc {
         self.periph.rcc_cfgr_sws.read_bits() as u32
     }
 
+    /// Read the AHB prescaler from mcu.
+    pub fn read_hpre(&self) -> u32 {
+        self.periph.rcc_cfgr_hpre.read_bits() as u32
+    }
+
+    /// Read the APB1 prescaler from mcu.
+    pub fn read_ppre1(&self) -> u32 {
+        self.periph.rcc_cfgr_ppre1.read_bits() as u32
+    }
+
+    /// Read the APB2 prescaler from mcu.
+    pub fn read_ppre2(&self) -> u32 {
+        self.periph.rcc_cfgr_ppre2.read_bits() as u32
+    }
+
         /// Power interface clock enable.
     #[inline]
     pub fn set_apb1enr_pwren(&self) -> () {
@@ -0,0 +1,252 @@
+//! Clock ready flags.
+//!
+//! Lets the oscillator drivers await their ready flag instead of spinning on
+//! it. The flags are handled by the `rcc` interrupt thread. All waits are
+//! bounded by counting CPU cycles at the live HCLK, so they work before
+//! SysTick is set up and leave it alone.
+
+use crate::drv::flash::FlashError;
+use crate::periph::rcc_ready::RccReadyPeriph;
+use crate::sys::system::System;
+use crate::tasks::root::SystemRes;
+use crate::thr;
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use drone_core::reg::tag::Crt;
+use drone_cortexm::{
+    fib::{self, FiberFuture},
+    processor,
+    reg::prelude::*,
+    thr::prelude::*,
+};
+use drone_stm32_map::reg::rcc::cir;
+use futures::{
+    future::{self, Either},
+    prelude::*,
+};
+
+/// A clock source with a ready interrupt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadySource {
+    /// 40 kHz low speed internal RC.
+    Lsi,
+    /// 32.768 kHz low speed external resonator.
+    Lse,
+    /// 8 MHz high speed internal RC.
+    Hsi,
+    /// High speed external clock.
+    Hse,
+    /// Phase-locked loop.
+    Pll,
+}
+
+/// Time given to the HSI to start or stop, in milliseconds.
+pub const HSI_TIMEOUT: u32 = 5;
+/// Time given to the HSE to start or stop, in milliseconds.
+pub const HSE_TIMEOUT: u32 = 100;
+/// Time given to the LSE to start, in milliseconds.
+pub const LSE_TIMEOUT: u32 = 5_000;
+/// Time given to the LSI to start, in milliseconds.
+pub const LSI_TIMEOUT: u32 = 5;
+/// Time given to the PLL to lock or unlock, in milliseconds.
+pub const PLL_TIMEOUT: u32 = 5;
+
+/// CPU cycles spent between two polls in [`poll_ready`].
+const POLL_CYCLES: u32 = 64;
+
+/// An error returned when a clock didn't reach the expected state in time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockError {
+    /// HSI didn't start or stop.
+    HsiTimeout,
+    /// HSE didn't start or stop.
+    HseTimeout,
+    /// LSE didn't start.
+    LseTimeout,
+    /// LSI didn't start.
+    LsiTimeout,
+    /// PLL didn't lock or unlock.
+    PllTimeout,
+    /// The flash isn't set up for the new HCLK.
+    Flash(FlashError),
+}
+
+impl From<FlashError> for ClockError {
+    fn from(err: FlashError) -> Self {
+        ClockError::Flash(err)
+    }
+}
+
+/// Polls `ready` until it returns `true`, for at least `timeout`
+/// milliseconds, and returns `error` otherwise.
+///
+/// The time is counted in CPU cycles at `hclk`, which must be the live HCLK,
+/// so this doesn't need SysTick, which may not be set up yet.
+pub fn poll_ready<E>(
+    timeout: u32,
+    hclk: u32,
+    error: E,
+    mut ready: impl FnMut() -> bool,
+) -> Result<(), E> {
+    for _ in 0..=polls_for(timeout, hclk) {
+        if ready() {
+            return Ok(());
+        }
+        processor::spin(POLL_CYCLES);
+    }
+    Err(error)
+}
+
+/// Returns the number of polls spaced by [`POLL_CYCLES`] which last
+/// `timeout` milliseconds at `hclk`.
+fn polls_for(timeout: u32, hclk: u32) -> u64 {
+    u64::from(timeout) * u64::from(hclk / 1000) / u64::from(POLL_CYCLES)
+}
+
+/// A future which completes after a number of polls, spinning
+/// [`POLL_CYCLES`] on each. It wakes itself up again right away, so it
+/// bounds a wait in CPU cycles like [`poll_ready`], while the other fibers
+/// of the thread keep running.
+struct CycleDeadline {
+    polls: u64,
+}
+
+impl Future for CycleDeadline {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.polls == 0 {
+            return Poll::Ready(());
+        }
+        self.polls -= 1;
+        processor::spin(POLL_CYCLES);
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// Clock ready interrupt driver.
+pub struct RccReady {
+    rcc_cir_lsirdyf: cir::Lsirdyf<Crt>,
+    rcc_cir_lserdyf: cir::Lserdyf<Crt>,
+    rcc_cir_hsirdyf: cir::Hsirdyf<Crt>,
+    rcc_cir_hserdyf: cir::Hserdyf<Crt>,
+    rcc_cir_pllrdyf: cir::Pllrdyf<Crt>,
+    rcc_cir_lsirdyie: cir::Lsirdyie<Crt>,
+    rcc_cir_lserdyie: cir::Lserdyie<Crt>,
+    rcc_cir_hsirdyie: cir::Hsirdyie<Crt>,
+    rcc_cir_hserdyie: cir::Hserdyie<Crt>,
+    rcc_cir_pllrdyie: cir::Pllrdyie<Crt>,
+    rcc_cir_lsirdyc: cir::Lsirdyc<Crt>,
+    rcc_cir_lserdyc: cir::Lserdyc<Crt>,
+    rcc_cir_hsirdyc: cir::Hsirdyc<Crt>,
+    rcc_cir_hserdyc: cir::Hserdyc<Crt>,
+    rcc_cir_pllrdyc: cir::Pllrdyc<Crt>,
+}
+
+impl RccReady {
+    /// Creates a new [`RccReady`].
+    pub fn new(periph: RccReadyPeriph) -> Self {
+        Self {
+            rcc_cir_lsirdyf: periph.rcc_cir_lsirdyf.into_copy(),
+            rcc_cir_lserdyf: periph.rcc_cir_lserdyf.into_copy(),
+            rcc_cir_hsirdyf: periph.rcc_cir_hsirdyf.into_copy(),
+            rcc_cir_hserdyf: periph.rcc_cir_hserdyf.into_copy(),
+            rcc_cir_pllrdyf: periph.rcc_cir_pllrdyf.into_copy(),
+            rcc_cir_lsirdyie: periph.rcc_cir_lsirdyie.into_copy(),
+            rcc_cir_lserdyie: periph.rcc_cir_lserdyie.into_copy(),
+            rcc_cir_hsirdyie: periph.rcc_cir_hsirdyie.into_copy(),
+            rcc_cir_hserdyie: periph.rcc_cir_hserdyie.into_copy(),
+            rcc_cir_pllrdyie: periph.rcc_cir_pllrdyie.into_copy(),
+            rcc_cir_lsirdyc: periph.rcc_cir_lsirdyc.into_copy(),
+            rcc_cir_lserdyc: periph.rcc_cir_lserdyc.into_copy(),
+            rcc_cir_hsirdyc: periph.rcc_cir_hsirdyc.into_copy(),
+            rcc_cir_hserdyc: periph.rcc_cir_hserdyc.into_copy(),
+            rcc_cir_pllrdyc: periph.rcc_cir_pllrdyc.into_copy(),
+        }
+    }
+
+    /// Turns `source` on with `turn_on` and waits on the `rcc` thread until
+    /// `is_ready` returns `true`, for at most `timeout` milliseconds counted
+    /// in CPU cycles at the live HCLK. Returns `error` on timeout.
+    pub async fn wait<E>(
+        &self,
+        res: &SystemRes,
+        source: ReadySource,
+        timeout: u32,
+        error: E,
+        turn_on: impl FnOnce(),
+        is_ready: impl Fn() -> bool,
+    ) -> Result<(), E> {
+        if is_ready() {
+            return Ok(());
+        }
+        let deadline = CycleDeadline { polls: polls_for(timeout, System::calculate_hclk(res)) };
+        let ready = self.arm(res.thr_rcc, source);
+        turn_on();
+        if let Either::Right(_) = future::select(ready, deadline).await {
+            self.disarm(res.thr_rcc, source);
+        }
+        if is_ready() {
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+
+    /// Arms the ready interrupt of `source` and returns a future which
+    /// completes once the `rcc` thread saw the ready flag, or once the
+    /// interrupt was disarmed with [`RccReady::disarm`].
+    ///
+    /// Call it before turning the clock on, so the flag can't be missed.
+    fn arm(&self, rcc: thr::Rcc, source: ReadySource) -> FiberFuture<()> {
+        macro_rules! arm {
+            ($rdyf:ident, $rdyie:ident, $rdyc:ident) => {{
+                let rdyf = self.$rdyf;
+                let rdyie = self.$rdyie;
+                let rdyc = self.$rdyc;
+                // RCC_CIR is also modified by the rcc thread.
+                rcc.disable_int();
+                let ready = rcc.add_future(fib::new_fn(move || {
+                    if !rdyie.read_bit() {
+                        fib::Complete(())
+                    } else if rdyf.read_bit() {
+                        rdyc.set_bit();
+                        rdyie.clear_bit();
+                        fib::Complete(())
+                    } else {
+                        fib::Yielded(())
+                    }
+                }));
+                rdyc.set_bit();
+                rdyie.set_bit();
+                rcc.enable_int();
+                ready
+            }};
+        }
+        match source {
+            ReadySource::Lsi => arm!(rcc_cir_lsirdyf, rcc_cir_lsirdyie, rcc_cir_lsirdyc),
+            ReadySource::Lse => arm!(rcc_cir_lserdyf, rcc_cir_lserdyie, rcc_cir_lserdyc),
+            ReadySource::Hsi => arm!(rcc_cir_hsirdyf, rcc_cir_hsirdyie, rcc_cir_hsirdyc),
+            ReadySource::Hse => arm!(rcc_cir_hserdyf, rcc_cir_hserdyie, rcc_cir_hserdyc),
+            ReadySource::Pll => arm!(rcc_cir_pllrdyf, rcc_cir_pllrdyie, rcc_cir_pllrdyc),
+        }
+    }
+
+    /// Disarms the ready interrupt of `source` and lets the fiber added by
+    /// [`RccReady::arm`] finish.
+    fn disarm(&self, rcc: thr::Rcc, source: ReadySource) {
+        rcc.disable_int();
+        match source {
+            ReadySource::Lsi => self.rcc_cir_lsirdyie.clear_bit(),
+            ReadySource::Lse => self.rcc_cir_lserdyie.clear_bit(),
+            ReadySource::Hsi => self.rcc_cir_hsirdyie.clear_bit(),
+            ReadySource::Hse => self.rcc_cir_hserdyie.clear_bit(),
+            ReadySource::Pll => self.rcc_cir_pllrdyie.clear_bit(),
+        }
+        rcc.set_pending();
+        rcc.enable_int();
+    }
+}
@@ -0,0 +1,464 @@
+//! Real-time clock.
+//!
+//! The RTC lives in the backup domain and is clocked by the LSE or the LSI,
+//! so it keeps counting across clock mode switches and system resets. The
+//! calendar is kept in 24-hour format.
+
+use crate::drv::common::clear_w1c;
+use crate::drv::rcc_ready::{poll_ready, ClockError, LSI_TIMEOUT};
+use crate::periph::rtc::RtcPeriph;
+use crate::sys::clock_config::SYSCLK_MAX;
+use crate::sys::system::System;
+use crate::tasks::root::SystemRes;
+use crate::thr;
+use core::num::NonZeroUsize;
+use drone_core::reg::tag::{Crt, Srt};
+use drone_cortexm::{fib, reg::prelude::*, thr::prelude::*};
+use drone_stm32_map::reg;
+use futures::prelude::*;
+
+/// Time given to the RTC status flags, in milliseconds.
+///
+/// The RTC methods don't know the live HCLK, so the wait is counted at
+/// [`SYSCLK_MAX`], which only makes it longer at lower clocks.
+const RTC_TIMEOUT: u32 = 10;
+
+/// RTC clock source (field RCC_BDCR RTCSEL).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RtcClock {
+    /// 32.768 kHz low speed external resonator. Not fitted on the
+    /// NUCLEO-F303K8.
+    Lse = 0b01,
+    /// 40 kHz low speed internal RC, less accurate.
+    Lsi = 0b10,
+}
+
+impl RtcClock {
+    /// Returns the asynchronous and synchronous prescaler values which give
+    /// the 1 Hz calendar clock.
+    fn prediv(self) -> (u32, u32) {
+        match self {
+            RtcClock::Lse => (127, 255),
+            RtcClock::Lsi => (124, 319),
+        }
+    }
+}
+
+/// One of the two alarms.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Alarm {
+    /// Alarm A.
+    A,
+    /// Alarm B.
+    B,
+}
+
+/// RTC error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RtcError {
+    /// The RTC clock didn't start.
+    Clock(ClockError),
+    /// The RTC already runs from another clock. Only a backup domain reset
+    /// can change it.
+    ClockLocked,
+    /// The RTC didn't acknowledge a register access in time.
+    Timeout,
+    /// A date, time or alarm field is out of range.
+    OutOfRange,
+}
+
+impl From<ClockError> for RtcError {
+    fn from(err: ClockError) -> Self {
+        RtcError::Clock(err)
+    }
+}
+
+/// A calendar date and time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    /// Year within the century, 0 to 99.
+    pub year: u8,
+    /// Month, 1 to 12.
+    pub month: u8,
+    /// Day of the month, 1 to 31.
+    pub day: u8,
+    /// Day of the week, 1 (Monday) to 7 (Sunday).
+    pub weekday: u8,
+    /// Hours, 0 to 23.
+    pub hours: u8,
+    /// Minutes, 0 to 59.
+    pub minutes: u8,
+    /// Seconds, 0 to 59.
+    pub seconds: u8,
+}
+
+impl DateTime {
+    fn is_valid(&self) -> bool {
+        self.year <= 99
+            && (1..=12).contains(&self.month)
+            && (1..=31).contains(&self.day)
+            && (1..=7).contains(&self.weekday)
+            && self.hours <= 23
+            && self.minutes <= 59
+            && self.seconds <= 59
+    }
+}
+
+/// An alarm condition. A `None` field matches any value.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AlarmMatch {
+    /// Day of the month, 1 to 31.
+    pub day: Option<u8>,
+    /// Hours, 0 to 23.
+    pub hours: Option<u8>,
+    /// Minutes, 0 to 59.
+    pub minutes: Option<u8>,
+    /// Seconds, 0 to 59.
+    pub seconds: Option<u8>,
+}
+
+impl AlarmMatch {
+    fn is_valid(&self) -> bool {
+        self.day.map_or(true, |d| (1..=31).contains(&d))
+            && self.hours.map_or(true, |h| h <= 23)
+            && self.minutes.map_or(true, |m| m <= 59)
+            && self.seconds.map_or(true, |s| s <= 59)
+    }
+}
+
+/// RTC driver.
+pub struct Rtc {
+    rcc_bdcr_rtcsel: reg::rcc::bdcr::Rtcsel<Srt>,
+    rcc_bdcr_rtcen: reg::rcc::bdcr::Rtcen<Srt>,
+    rcc_csr_lsion: reg::rcc::csr::Lsion<Srt>,
+    rcc_csr_lsirdy: reg::rcc::csr::Lsirdy<Srt>,
+    rtc_tr: reg::rtc::Tr<Srt>,
+    rtc_dr: reg::rtc::Dr<Srt>,
+    rtc_cr: reg::rtc::Cr<Srt>,
+    rtc_isr: reg::rtc::Isr<Crt>,
+    rtc_prer: reg::rtc::Prer<Srt>,
+    rtc_wutr: reg::rtc::Wutr<Srt>,
+    rtc_alrmar: reg::rtc::Alrmar<Srt>,
+    rtc_alrmbr: reg::rtc::Alrmbr<Srt>,
+    rtc_wpr: reg::rtc::Wpr<Srt>,
+    exti_imr1_mr20: reg::exti::imr1::Mr20<Srt>,
+    exti_rtsr1_tr20: reg::exti::rtsr1::Tr20<Srt>,
+    exti_pr1_pr20: reg::exti::pr1::Pr20<Crt>,
+}
+
+impl Rtc {
+    /// Creates a new [`Rtc`].
+    #[inline]
+    pub fn new(periph: RtcPeriph) -> Self {
+        let RtcPeriph {
+            rcc_bdcr_rtcsel,
+            rcc_bdcr_rtcen,
+            rcc_csr_lsion,
+            rcc_csr_lsirdy,
+            rtc_tr,
+            rtc_dr,
+            rtc_cr,
+            rtc_isr,
+            rtc_prer,
+            rtc_wutr,
+            rtc_alrmar,
+            rtc_alrmbr,
+            rtc_wpr,
+            exti_imr1_mr20,
+            exti_rtsr1_tr20,
+            exti_pr1_pr20,
+        } = periph;
+        Self {
+            rcc_bdcr_rtcsel,
+            rcc_bdcr_rtcen,
+            rcc_csr_lsion,
+            rcc_csr_lsirdy,
+            rtc_tr,
+            rtc_dr,
+            rtc_cr,
+            rtc_isr: rtc_isr.into_copy(),
+            rtc_prer,
+            rtc_wutr,
+            rtc_alrmar,
+            rtc_alrmbr,
+            rtc_wpr,
+            exti_imr1_mr20,
+            exti_rtsr1_tr20,
+            exti_pr1_pr20: exti_pr1_pr20.into_copy(),
+        }
+    }
+
+    /// Starts `clock` and the RTC.
+    ///
+    /// If the RTC already runs from `clock`, e.g. after a system reset, the
+    /// calendar is left untouched. Starting the LSE may take seconds, the
+    /// wait is done on the `rcc` thread.
+    pub async fn init(&self, res: &SystemRes, clock: RtcClock) -> Result<(), RtcError> {
+        res.rcc.set_apb1enr_pwren();
+        res.rcc.set_pwr_cr_dbp();
+        match clock {
+            RtcClock::Lse => res.lse.init(res).await?,
+            RtcClock::Lsi => {
+                self.rcc_csr_lsion.set_bit();
+                let hclk = System::calculate_hclk(res);
+                poll_ready(LSI_TIMEOUT, hclk, ClockError::LsiTimeout, || {
+                    self.rcc_csr_lsirdy.read_bit()
+                })?;
+            }
+        }
+        let rtcsel = self.rcc_bdcr_rtcsel.read_bits() as u32;
+        if rtcsel == clock as u32 && self.rcc_bdcr_rtcen.read_bit() {
+            return Ok(());
+        }
+        if rtcsel != 0 && rtcsel != clock as u32 {
+            return Err(RtcError::ClockLocked);
+        }
+        self.rcc_bdcr_rtcsel.write_bits(clock as u32);
+        self.rcc_bdcr_rtcen.set_bit();
+        let (prediv_a, prediv_s) = clock.prediv();
+        self.configure(|| {
+            // Both prescalers are written in two separate accesses.
+            self.rtc_prer.store(|r| r.write_prediv_s(prediv_s));
+            self.rtc_prer.store(|r| r.write_prediv_s(prediv_s).write_prediv_a(prediv_a));
+            Ok(())
+        })
+    }
+
+    /// Sets the calendar.
+    pub fn set_date_time(&self, dt: &DateTime) -> Result<(), RtcError> {
+        if !dt.is_valid() {
+            return Err(RtcError::OutOfRange);
+        }
+        let (ht, hu) = bcd(dt.hours);
+        let (mnt, mnu) = bcd(dt.minutes);
+        let (st, su) = bcd(dt.seconds);
+        let (yt, yu) = bcd(dt.year);
+        let (mt, mu) = bcd(dt.month);
+        let mt = mt != 0;
+        let (dt_, du) = bcd(dt.day);
+        let weekday = u32::from(dt.weekday);
+        self.configure(|| {
+            self.rtc_tr.store(|r| {
+                r.clear_pm()
+                    .write_ht(ht)
+                    .write_hu(hu)
+                    .write_mnt(mnt)
+                    .write_mnu(mnu)
+                    .write_st(st)
+                    .write_su(su)
+            });
+            self.rtc_dr.store(|r| {
+                r.write_yt(yt)
+                    .write_yu(yu)
+                    .write_wdu(weekday)
+                    .write_mu(mu)
+                    .write_dt(dt_)
+                    .write_du(du);
+                if mt {
+                    r.set_mt();
+                } else {
+                    r.clear_mt();
+                }
+                r
+            });
+            self.rtc_cr.modify(|r| r.clear_fmt());
+            Ok(())
+        })
+    }
+
+    /// Reads the calendar.
+    pub fn date_time(&self) -> Result<DateTime, RtcError> {
+        // The shadow registers are copied from the calendar every two RTCCLK
+        // periods. Wait for a fresh copy.
+        self.unlocked(|| {
+            self.rtc_isr.modify(|r| r.clear_rsf());
+            Ok(())
+        })?;
+        poll_ready(RTC_TIMEOUT, SYSCLK_MAX, RtcError::Timeout, || self.rtc_isr.load().rsf())?;
+        // Reading TR freezes DR until it is read.
+        let tr = self.rtc_tr.load();
+        let dr = self.rtc_dr.load();
+        Ok(DateTime {
+            year: from_bcd(dr.yt(), dr.yu()),
+            month: from_bcd(dr.mt() as u32, dr.mu()),
+            day: from_bcd(dr.dt(), dr.du()),
+            weekday: dr.wdu() as u8,
+            hours: from_bcd(tr.ht(), tr.hu()),
+            minutes: from_bcd(tr.mnt(), tr.mnu()),
+            seconds: from_bcd(tr.st(), tr.su()),
+        })
+    }
+
+    /// Arms `alarm` to fire on `at`.
+    pub fn set_alarm(&self, alarm: Alarm, at: &AlarmMatch) -> Result<(), RtcError> {
+        if !at.is_valid() {
+            return Err(RtcError::OutOfRange);
+        }
+        let (dt, du) = bcd(at.day.unwrap_or(1));
+        let (ht, hu) = bcd(at.hours.unwrap_or(0));
+        let (mnt, mnu) = bcd(at.minutes.unwrap_or(0));
+        let (st, su) = bcd(at.seconds.unwrap_or(0));
+        self.unlocked(|| {
+            self.disable_alarm_locked(alarm)?;
+            macro_rules! write_alarm {
+                ($alrmr:ident) => {
+                    self.$alrmr.store(|r| {
+                        r.write_dt(dt)
+                            .write_du(du)
+                            .write_ht(ht)
+                            .write_hu(hu)
+                            .write_mnt(mnt)
+                            .write_mnu(mnu)
+                            .write_st(st)
+                            .write_su(su);
+                        if at.day.is_none() {
+                            r.set_msk4();
+                        }
+                        if at.hours.is_none() {
+                            r.set_msk3();
+                        }
+                        if at.minutes.is_none() {
+                            r.set_msk2();
+                        }
+                        if at.seconds.is_none() {
+                            r.set_msk1();
+                        }
+                        r
+                    })
+                };
+            }
+            match alarm {
+                Alarm::A => {
+                    write_alarm!(rtc_alrmar);
+                    self.rtc_isr.modify(|r| r.clear_alraf());
+                    self.rtc_cr.modify(|r| r.set_alrae());
+                }
+                Alarm::B => {
+                    write_alarm!(rtc_alrmbr);
+                    self.rtc_isr.modify(|r| r.clear_alrbf());
+                    self.rtc_cr.modify(|r| r.set_alrbe());
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Disarms `alarm`.
+    pub fn disable_alarm(&self, alarm: Alarm) -> Result<(), RtcError> {
+        self.unlocked(|| self.disable_alarm_locked(alarm))
+    }
+
+    /// Returns `true` and clears the flag if `alarm` fired since the last
+    /// call.
+    pub fn take_alarm(&self, alarm: Alarm) -> bool {
+        let isr = self.rtc_isr.load();
+        match alarm {
+            Alarm::A if isr.alraf() => {
+                self.rtc_isr.modify(|r| r.clear_alraf());
+                true
+            }
+            Alarm::B if isr.alrbf() => {
+                self.rtc_isr.modify(|r| r.clear_alrbf());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Starts the wakeup timer with a period of `seconds`, 1 to 65536.
+    pub fn enable_wakeup(&self, seconds: u32) -> Result<(), RtcError> {
+        if !(1..=0x1_0000).contains(&seconds) {
+            return Err(RtcError::OutOfRange);
+        }
+        self.unlocked(|| {
+            self.rtc_cr.modify(|r| r.clear_wute().clear_wutie());
+            poll_ready(RTC_TIMEOUT, SYSCLK_MAX, RtcError::Timeout, || self.rtc_isr.load().wutwf())?;
+            self.rtc_wutr.store(|r| r.write_wut(seconds - 1));
+            self.rtc_isr.modify(|r| r.clear_wutf());
+            clear_w1c(&self.exti_pr1_pr20);
+            self.exti_rtsr1_tr20.set_bit();
+            self.exti_imr1_mr20.set_bit();
+            // ck_spre, the 1 Hz calendar clock.
+            self.rtc_cr.modify(|r| r.write_wucksel(0b100).set_wutie().set_wute());
+            Ok(())
+        })
+    }
+
+    /// Stops the wakeup timer.
+    pub fn disable_wakeup(&self) -> Result<(), RtcError> {
+        self.unlocked(|| {
+            self.rtc_cr.modify(|r| r.clear_wute().clear_wutie());
+            self.exti_imr1_mr20.clear_bit();
+            Ok(())
+        })
+    }
+
+    /// Creates a new saturating stream of wakeup timer events, handled by
+    /// the `rtc_wkup` thread.
+    pub fn create_wakeup_stream(
+        &self,
+        rtc_wkup: thr::RtcWkup,
+    ) -> impl Stream<Item = NonZeroUsize> + Send + Sync {
+        let rtc_isr = self.rtc_isr;
+        let exti_pr1_pr20 = self.exti_pr1_pr20;
+        let stream = rtc_wkup.add_saturating_pulse_stream(fib::new_fn(move || {
+            if rtc_isr.load().wutf() {
+                rtc_isr.modify(|r| r.clear_wutf());
+                clear_w1c(&exti_pr1_pr20);
+                fib::Yielded(Some(1))
+            } else {
+                fib::Yielded(None)
+            }
+        }));
+        rtc_wkup.enable_int();
+        stream
+    }
+
+    fn disable_alarm_locked(&self, alarm: Alarm) -> Result<(), RtcError> {
+        match alarm {
+            Alarm::A => {
+                self.rtc_cr.modify(|r| r.clear_alrae());
+                poll_ready(RTC_TIMEOUT, SYSCLK_MAX, RtcError::Timeout, || {
+                    self.rtc_isr.load().alrawf()
+                })
+            }
+            Alarm::B => {
+                self.rtc_cr.modify(|r| r.clear_alrbe());
+                poll_ready(RTC_TIMEOUT, SYSCLK_MAX, RtcError::Timeout, || {
+                    self.rtc_isr.load().alrbwf()
+                })
+            }
+        }
+    }
+
+    /// Runs `f` with the RTC registers write-enabled.
+    fn unlocked(&self, f: impl FnOnce() -> Result<(), RtcError>) -> Result<(), RtcError> {
+        self.rtc_wpr.store(|r| r.write_key(0xCA));
+        self.rtc_wpr.store(|r| r.write_key(0x53));
+        let result = f();
+        self.rtc_wpr.store(|r| r.write_key(0xFF));
+        result
+    }
+
+    /// Runs `f` in initialization mode, with the calendar stopped.
+    fn configure(&self, f: impl FnOnce() -> Result<(), RtcError>) -> Result<(), RtcError> {
+        self.unlocked(|| {
+            self.rtc_isr.modify(|r| r.set_init());
+            let result = poll_ready(RTC_TIMEOUT, SYSCLK_MAX, RtcError::Timeout, || {
+                self.rtc_isr.load().initf()
+            })
+            .and_then(|()| f());
+            self.rtc_isr.modify(|r| r.clear_init().clear_rsf());
+            result
+        })
+    }
+}
+
+/// Splits `value` into BCD tens and units.
+fn bcd(value: u8) -> (u32, u32) {
+    (u32::from(value / 10), u32::from(value % 10))
+}
+
+fn from_bcd(tens: u32, units: u32) -> u8 {
+    (tens * 10 + units) as u8
+}
@@ -1,6 +1,8 @@
 #![feature(llvm_asm)]
 #![feature(allocator_api)]
 #![feature(const_fn_fn_ptr_basics)]
+#![feature(const_panic)]
+#![feature(core_intrinsics)]
 #![feature(prelude_import)]
 #![feature(proc_macro_hygiene)]
 #![feature(slice_ptr_get)]
@@ -15,6 +17,7 @@ pub mod periph;
 #[macro_use]
 pub mod sys;
 
+pub mod board;
 pub mod consts;
 pub mod tasks;
 pub mod thr;
@@ -0,0 +1,33 @@
+//! RTC backup registers.
+
+use drone_core::periph;
+
+periph::singular! {
+    /// Extracts backup register tokens.
+    pub macro periph_backup;
+
+    /// Backup registers peripheral.
+    pub struct BackupPeriph;
+
+    drone_stm32_map::reg;
+    crate::periph::backup;
+
+    RTC {
+        BKP0R;
+        BKP1R;
+        BKP2R;
+        BKP3R;
+        BKP4R;
+        BKP5R;
+        BKP6R;
+        BKP7R;
+        BKP8R;
+        BKP9R;
+        BKP10R;
+        BKP11R;
+        BKP12R;
+        BKP13R;
+        BKP14R;
+        BKP15R;
+    }
+}
@@ -0,0 +1,24 @@
+//! Clock Security System.
+
+use drone_core::periph;
+
+periph::singular! {
+    /// Extracts CSS register tokens.
+    pub macro periph_css;
+
+    /// CSS peripheral.
+    pub struct CssPeriph;
+
+    drone_stm32_map::reg;
+    crate::periph::css;
+
+    RCC {
+        CR {
+            CSSON;
+        }
+        CIR {
+            CSSF;
+            CSSC;
+        }
+    }
+}
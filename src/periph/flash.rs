@@ -14,5 +14,10 @@ periph::singular! {
 
     FLASH {
         ACR;
+        KEYR;
+        OPTKEYR;
+        SR;
+        CR;
+        AR;
     }
 }
@@ -0,0 +1,22 @@
+//! High Speed External clock.
+
+use drone_core::periph;
+
+periph::singular! {
+    /// Extracts HSE register tokens.
+    pub macro periph_hse;
+
+    /// HSE peripheral.
+    pub struct HsePeriph;
+
+    drone_stm32_map::reg;
+    crate::periph::hse;
+
+    RCC {
+        CR {
+            HSEON;
+            HSERDY;
+            HSEBYP;
+        }
+    }
+}
@@ -16,6 +16,8 @@ periph::singular! {
         CR {
             HSION;
             HSIRDY;
+            HSITRIM;
+            HSICAL;
         }
     }
 }
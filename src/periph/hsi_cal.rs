@@ -0,0 +1,51 @@
+//! HSI calibration timer.
+
+use drone_core::periph;
+
+periph::singular! {
+    /// Extracts HSI calibration register tokens.
+    pub macro periph_hsi_cal;
+
+    /// HSI calibration peripheral.
+    pub struct HsiCalPeriph;
+
+    drone_stm32_map::reg;
+    crate::periph::hsi_cal;
+
+    RCC {
+        APB2ENR {
+            TIM16EN;
+        }
+    }
+
+    TIM16 {
+        CR1 {
+            CEN;
+        }
+        SR {
+            CC1IF;
+            CC1OF;
+        }
+        CCMR1_Input {
+            CC1S;
+            IC1PSC;
+            IC1F;
+        }
+        CCER {
+            CC1E;
+            CC1P;
+        }
+        PSC {
+            PSC;
+        }
+        ARR {
+            ARR;
+        }
+        CCR1 {
+            CCR1;
+        }
+        OR {
+            TI1_RMP;
+        }
+    }
+}
@@ -0,0 +1,22 @@
+//! Microcontroller clock output.
+
+use drone_core::periph;
+
+periph::singular! {
+    /// Extracts MCO register tokens.
+    pub macro periph_mco;
+
+    /// MCO peripheral.
+    pub struct McoPeriph;
+
+    drone_stm32_map::reg;
+    crate::periph::mco;
+
+    RCC {
+        CFGR {
+            MCO;
+            MCOPRE;
+            PLLNODIV;
+        }
+    }
+}
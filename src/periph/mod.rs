@@ -1,12 +1,26 @@
 //! Peripherals.
 
+#[macro_use]
+pub mod backup;
+#[macro_use]
+pub mod css;
 #[macro_use]
 pub mod flash;
 #[macro_use]
+pub mod hse;
+#[macro_use]
 pub mod lse;
 #[macro_use]
+pub mod mco;
+#[macro_use]
 pub mod hsi;
 #[macro_use]
+pub mod hsi_cal;
+#[macro_use]
 pub mod pll;
 #[macro_use]
 pub mod rcc;
+#[macro_use]
+pub mod rcc_ready;
+#[macro_use]
+pub mod rtc;
@@ -21,6 +21,10 @@ periph::singular! {
             PLLSRC; 
             PLLMUL; 
         }
+        CFGR2 {
+            PREDIV;
+            ADC12PRES;
+        }
     }
 }
 
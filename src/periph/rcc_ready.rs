@@ -0,0 +1,34 @@
+//! Clock ready interrupts.
+
+use drone_core::periph;
+
+periph::singular! {
+    /// Extracts clock ready interrupt register tokens.
+    pub macro periph_rcc_ready;
+
+    /// Clock ready interrupt peripheral.
+    pub struct RccReadyPeriph;
+
+    drone_stm32_map::reg;
+    crate::periph::rcc_ready;
+
+    RCC {
+        CIR {
+            LSIRDYF;
+            LSERDYF;
+            HSIRDYF;
+            HSERDYF;
+            PLLRDYF;
+            LSIRDYIE;
+            LSERDYIE;
+            HSIRDYIE;
+            HSERDYIE;
+            PLLRDYIE;
+            LSIRDYC;
+            LSERDYC;
+            HSIRDYC;
+            HSERDYC;
+            PLLRDYC;
+        }
+    }
+}
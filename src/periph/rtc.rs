@@ -0,0 +1,49 @@
+//! Real-time clock.
+
+use drone_core::periph;
+
+periph::singular! {
+    /// Extracts RTC register tokens.
+    pub macro periph_rtc;
+
+    /// RTC peripheral.
+    pub struct RtcPeriph;
+
+    drone_stm32_map::reg;
+    crate::periph::rtc;
+
+    RCC {
+        BDCR {
+            RTCSEL;
+            RTCEN;
+        }
+        CSR {
+            LSION;
+            LSIRDY;
+        }
+    }
+
+    RTC {
+        TR;
+        DR;
+        CR;
+        ISR;
+        PRER;
+        WUTR;
+        ALRMAR;
+        ALRMBR;
+        WPR;
+    }
+
+    EXTI {
+        IMR1 {
+            MR20;
+        }
+        RTSR1 {
+            TR20;
+        }
+        PR1 {
+            PR20;
+        }
+    }
+}
@@ -0,0 +1,844 @@
+//! Typed clock tree configuration.
+//!
+//! A [`ClockConfig`] can only be created through [`ClockConfigBuilder`],
+//! which rejects selector combinations that would run the MCU out of its
+//! specification (see RM0316 and the DS9866 datasheet).
+
+use crate::consts::{HSE_STLINK_MCO_CLK, HSI_CLK};
+
+/// Maximum SYSCLK and PLL output frequency.
+pub const SYSCLK_MAX: u32 = 72_000_000;
+
+/// Minimum PLL output frequency.
+pub const PLL_OUT_MIN: u32 = 16_000_000;
+
+/// Minimum PLL input frequency.
+pub const PLL_IN_MIN: u32 = 1_000_000;
+
+/// Maximum PLL input frequency.
+pub const PLL_IN_MAX: u32 = 24_000_000;
+
+/// Minimum HSE crystal frequency.
+pub const HSE_OSC_MIN: u32 = 4_000_000;
+
+/// Minimum HSE frequency in bypass mode.
+pub const HSE_BYPASS_MIN: u32 = 1_000_000;
+
+/// Maximum HSE frequency.
+pub const HSE_MAX: u32 = 32_000_000;
+
+/// Maximum APB1 (low-speed) bus frequency.
+pub const PCLK1_MAX: u32 = 36_000_000;
+
+/// Maximum APB2 (high-speed) bus frequency.
+pub const PCLK2_MAX: u32 = 72_000_000;
+
+/// System clock switch (field RCC_CFGR SW).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SysClkSrc {
+    /// HSI oscillator used as system clock.
+    Hsi = 0b00,
+    /// HSE oscillator used as system clock.
+    Hse = 0b01,
+    /// PLL used as system clock.
+    Pll = 0b10,
+}
+
+/// HSE operating mode (field RCC_CR HSEBYP).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HseMode {
+    /// Crystal or ceramic resonator between OSC_IN and OSC_OUT.
+    Oscillator,
+    /// External clock signal fed into OSC_IN.
+    Bypass,
+}
+
+/// HSE source description.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HseConfig {
+    /// Operating mode.
+    pub mode: HseMode,
+    /// Frequency of the crystal or the external clock.
+    pub freq: u32,
+}
+
+impl Default for HseConfig {
+    /// The 8 MHz MCO output of the ST-LINK in bypass mode.
+    fn default() -> Self {
+        Self { mode: HseMode::Bypass, freq: HSE_STLINK_MCO_CLK }
+    }
+}
+
+/// PLL entry clock source (field RCC_CFGR PLLSRC).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PllSrc {
+    /// HSI/2 selected as PLL input clock.
+    HsiDiv2 = 0b00,
+    /// HSE/PREDIV selected as PLL input clock.
+    HsePrediv = 0b01,
+}
+
+/// PLL multiplication factor (field RCC_CFGR PLLMUL).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PllMul {
+    /// PLL input clock x 2.
+    Mul2 = 0b0000,
+    /// PLL input clock x 3.
+    Mul3 = 0b0001,
+    /// PLL input clock x 4.
+    Mul4 = 0b0010,
+    /// PLL input clock x 5.
+    Mul5 = 0b0011,
+    /// PLL input clock x 6.
+    Mul6 = 0b0100,
+    /// PLL input clock x 7.
+    Mul7 = 0b0101,
+    /// PLL input clock x 8.
+    Mul8 = 0b0110,
+    /// PLL input clock x 9.
+    Mul9 = 0b0111,
+    /// PLL input clock x 10.
+    Mul10 = 0b1000,
+    /// PLL input clock x 11.
+    Mul11 = 0b1001,
+    /// PLL input clock x 12.
+    Mul12 = 0b1010,
+    /// PLL input clock x 13.
+    Mul13 = 0b1011,
+    /// PLL input clock x 14.
+    Mul14 = 0b1100,
+    /// PLL input clock x 15.
+    Mul15 = 0b1101,
+    /// PLL input clock x 16.
+    Mul16 = 0b1110,
+}
+
+/// Division factor of the AHB clock (field RCC_CFGR HPRE).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AhbPrescaler {
+    /// SYSCLK not divided.
+    Div1 = 0b0000,
+    /// SYSCLK divided by 2.
+    Div2 = 0b1000,
+    /// SYSCLK divided by 4.
+    Div4 = 0b1001,
+    /// SYSCLK divided by 8.
+    Div8 = 0b1010,
+    /// SYSCLK divided by 16.
+    Div16 = 0b1011,
+    /// SYSCLK divided by 64.
+    Div64 = 0b1100,
+    /// SYSCLK divided by 128.
+    Div128 = 0b1101,
+    /// SYSCLK divided by 256.
+    Div256 = 0b1110,
+    /// SYSCLK divided by 512.
+    Div512 = 0b1111,
+}
+
+/// Division factor of an APB clock (fields RCC_CFGR PPRE1 and PPRE2).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApbPrescaler {
+    /// HCLK not divided.
+    Div1 = 0b000,
+    /// HCLK divided by 2.
+    Div2 = 0b100,
+    /// HCLK divided by 4.
+    Div4 = 0b101,
+    /// HCLK divided by 8.
+    Div8 = 0b110,
+    /// HCLK divided by 16.
+    Div16 = 0b111,
+}
+
+/// HSE division factor for the PLL input (field RCC_CFGR2 PREDIV).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Prediv {
+    /// HSE input to PLL not divided.
+    Div1 = 0b0000,
+    /// HSE input to PLL divided by 2.
+    Div2 = 0b0001,
+    /// HSE input to PLL divided by 3.
+    Div3 = 0b0010,
+    /// HSE input to PLL divided by 4.
+    Div4 = 0b0011,
+    /// HSE input to PLL divided by 5.
+    Div5 = 0b0100,
+    /// HSE input to PLL divided by 6.
+    Div6 = 0b0101,
+    /// HSE input to PLL divided by 7.
+    Div7 = 0b0110,
+    /// HSE input to PLL divided by 8.
+    Div8 = 0b0111,
+    /// HSE input to PLL divided by 9.
+    Div9 = 0b1000,
+    /// HSE input to PLL divided by 10.
+    Div10 = 0b1001,
+    /// HSE input to PLL divided by 11.
+    Div11 = 0b1010,
+    /// HSE input to PLL divided by 12.
+    Div12 = 0b1011,
+    /// HSE input to PLL divided by 13.
+    Div13 = 0b1100,
+    /// HSE input to PLL divided by 14.
+    Div14 = 0b1101,
+    /// HSE input to PLL divided by 15.
+    Div15 = 0b1110,
+    /// HSE input to PLL divided by 16.
+    Div16 = 0b1111,
+}
+
+/// ADC12 prescaler (field RCC_CFGR2 ADC12PRES).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Adc12Prescaler {
+    /// ADC12 clock from the PLL disabled (the ADC may use the AHB clock).
+    Off = 0b00000,
+    /// PLL clock divided by 1.
+    Div1 = 0b10000,
+    /// PLL clock divided by 2.
+    Div2 = 0b10001,
+    /// PLL clock divided by 4.
+    Div4 = 0b10010,
+    /// PLL clock divided by 6.
+    Div6 = 0b10011,
+    /// PLL clock divided by 8.
+    Div8 = 0b10100,
+    /// PLL clock divided by 10.
+    Div10 = 0b10101,
+    /// PLL clock divided by 12.
+    Div12 = 0b10110,
+    /// PLL clock divided by 16.
+    Div16 = 0b10111,
+    /// PLL clock divided by 32.
+    Div32 = 0b11000,
+    /// PLL clock divided by 64.
+    Div64 = 0b11001,
+    /// PLL clock divided by 128.
+    Div128 = 0b11010,
+    /// PLL clock divided by 256.
+    Div256 = 0b11011,
+}
+
+impl SysClkSrc {
+    /// Returns the register encoding.
+    #[inline]
+    pub fn bits(self) -> u32 {
+        self as u32
+    }
+
+    /// Decodes the register encoding, e.g. the value of RCC_CFGR SWS.
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            0b00 => Some(Self::Hsi),
+            0b01 => Some(Self::Hse),
+            0b10 => Some(Self::Pll),
+            _ => None,
+        }
+    }
+}
+
+impl PllSrc {
+    /// Returns the register encoding.
+    #[inline]
+    pub fn bits(self) -> u32 {
+        self as u32
+    }
+
+    /// Decodes the register encoding.
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            0b00 => Some(Self::HsiDiv2),
+            0b01 => Some(Self::HsePrediv),
+            _ => None,
+        }
+    }
+}
+
+impl PllMul {
+    /// All multiplication factors, from x 2 to x 16.
+    pub const ALL: [Self; 15] = [
+        Self::Mul2,
+        Self::Mul3,
+        Self::Mul4,
+        Self::Mul5,
+        Self::Mul6,
+        Self::Mul7,
+        Self::Mul8,
+        Self::Mul9,
+        Self::Mul10,
+        Self::Mul11,
+        Self::Mul12,
+        Self::Mul13,
+        Self::Mul14,
+        Self::Mul15,
+        Self::Mul16,
+    ];
+
+    /// Returns the register encoding.
+    #[inline]
+    pub fn bits(self) -> u32 {
+        self as u32
+    }
+
+    /// Returns the multiplication factor.
+    #[inline]
+    pub fn factor(self) -> u32 {
+        self as u32 + 2
+    }
+
+    /// Decodes the register encoding.
+    ///
+    /// The hardware treats `0b1111` the same as `0b1110` (x 16).
+    pub fn from_bits(bits: u32) -> Self {
+        Self::ALL[bits.min(0b1110) as usize]
+    }
+
+    /// Returns the variant for the multiplication `factor`, if it exists.
+    pub fn from_factor(factor: u32) -> Option<Self> {
+        match factor {
+            2..=16 => Some(Self::ALL[(factor - 2) as usize]),
+            _ => None,
+        }
+    }
+}
+
+impl AhbPrescaler {
+    /// All AHB prescalers, from the smallest to the largest division factor.
+    pub const ALL: [Self; 9] = [
+        Self::Div1,
+        Self::Div2,
+        Self::Div4,
+        Self::Div8,
+        Self::Div16,
+        Self::Div64,
+        Self::Div128,
+        Self::Div256,
+        Self::Div512,
+    ];
+
+    /// Returns the register encoding.
+    #[inline]
+    pub fn bits(self) -> u32 {
+        self as u32
+    }
+
+    /// Returns the division factor.
+    pub fn divisor(self) -> u32 {
+        match self {
+            Self::Div1 => 1,
+            Self::Div2 => 2,
+            Self::Div4 => 4,
+            Self::Div8 => 8,
+            Self::Div16 => 16,
+            Self::Div64 => 64,
+            Self::Div128 => 128,
+            Self::Div256 => 256,
+            Self::Div512 => 512,
+        }
+    }
+
+    /// Decodes the register encoding (`0xxx` means not divided).
+    pub fn from_bits(bits: u32) -> Self {
+        match bits {
+            0b1000 => Self::Div2,
+            0b1001 => Self::Div4,
+            0b1010 => Self::Div8,
+            0b1011 => Self::Div16,
+            0b1100 => Self::Div64,
+            0b1101 => Self::Div128,
+            0b1110 => Self::Div256,
+            0b1111 => Self::Div512,
+            _ => Self::Div1,
+        }
+    }
+}
+
+impl ApbPrescaler {
+    const ALL: [Self; 5] = [Self::Div1, Self::Div2, Self::Div4, Self::Div8, Self::Div16];
+
+    /// Returns the register encoding.
+    #[inline]
+    pub fn bits(self) -> u32 {
+        self as u32
+    }
+
+    /// Returns the smallest prescaler which keeps `hclk` divided at or below
+    /// `max`.
+    pub fn for_max(hclk: u32, max: u32) -> Option<Self> {
+        Self::ALL.iter().copied().find(|ppre| hclk / ppre.divisor() <= max)
+    }
+
+    /// Returns the division factor.
+    pub fn divisor(self) -> u32 {
+        match self {
+            Self::Div1 => 1,
+            Self::Div2 => 2,
+            Self::Div4 => 4,
+            Self::Div8 => 8,
+            Self::Div16 => 16,
+        }
+    }
+
+    /// Decodes the register encoding (`0xx` means not divided).
+    pub fn from_bits(bits: u32) -> Self {
+        match bits {
+            0b100 => Self::Div2,
+            0b101 => Self::Div4,
+            0b110 => Self::Div8,
+            0b111 => Self::Div16,
+            _ => Self::Div1,
+        }
+    }
+}
+
+impl Prediv {
+    /// All division factors, from / 1 to / 16.
+    pub const ALL: [Self; 16] = [
+        Self::Div1,
+        Self::Div2,
+        Self::Div3,
+        Self::Div4,
+        Self::Div5,
+        Self::Div6,
+        Self::Div7,
+        Self::Div8,
+        Self::Div9,
+        Self::Div10,
+        Self::Div11,
+        Self::Div12,
+        Self::Div13,
+        Self::Div14,
+        Self::Div15,
+        Self::Div16,
+    ];
+
+    /// Returns the register encoding.
+    #[inline]
+    pub fn bits(self) -> u32 {
+        self as u32
+    }
+
+    /// Returns the division factor.
+    #[inline]
+    pub fn divisor(self) -> u32 {
+        self as u32 + 1
+    }
+
+    /// Decodes the register encoding.
+    pub fn from_bits(bits: u32) -> Self {
+        Self::ALL[(bits & 0b1111) as usize]
+    }
+}
+
+impl Adc12Prescaler {
+    /// Returns the register encoding.
+    #[inline]
+    pub fn bits(self) -> u32 {
+        self as u32
+    }
+
+    /// Returns the division factor, or `None` if the PLL clock is not used.
+    pub fn divisor(self) -> Option<u32> {
+        match self {
+            Self::Off => None,
+            Self::Div1 => Some(1),
+            Self::Div2 => Some(2),
+            Self::Div4 => Some(4),
+            Self::Div6 => Some(6),
+            Self::Div8 => Some(8),
+            Self::Div10 => Some(10),
+            Self::Div12 => Some(12),
+            Self::Div16 => Some(16),
+            Self::Div32 => Some(32),
+            Self::Div64 => Some(64),
+            Self::Div128 => Some(128),
+            Self::Div256 => Some(256),
+        }
+    }
+
+    /// Decodes the register encoding (`0xxxx` means off, `111xx` and
+    /// `11011` both divide by 256).
+    pub fn from_bits(bits: u32) -> Self {
+        match bits {
+            0b10000 => Self::Div1,
+            0b10001 => Self::Div2,
+            0b10010 => Self::Div4,
+            0b10011 => Self::Div6,
+            0b10100 => Self::Div8,
+            0b10101 => Self::Div10,
+            0b10110 => Self::Div12,
+            0b10111 => Self::Div16,
+            0b11000 => Self::Div32,
+            0b11001 => Self::Div64,
+            0b11010 => Self::Div128,
+            0b11011..=0b11111 => Self::Div256,
+            _ => Self::Off,
+        }
+    }
+}
+
+/// An error returned when a clock configuration is out of specification.
+#[derive(Debug)]
+pub enum ClockConfigError {
+    /// HSE is selected but was not described with
+    /// [`ClockConfigBuilder::hse`].
+    HseMissing,
+    /// The HSE frequency is outside of the range of its mode.
+    HseOutOfRange(u32),
+    /// The PLL input frequency is outside of 1..=24 MHz.
+    PllInputOutOfRange(u32),
+    /// The PLL output frequency is outside of 16..=72 MHz.
+    PllOutputOutOfRange(u32),
+    /// SYSCLK would exceed 72 MHz.
+    SysclkTooHigh(u32),
+    /// APB1 clock would exceed 36 MHz.
+    Pclk1TooHigh(u32),
+    /// APB2 clock would exceed 72 MHz.
+    Pclk2TooHigh(u32),
+}
+
+/// A validated clock tree configuration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClockConfig {
+    sysclk_src: SysClkSrc,
+    pll_src: PllSrc,
+    pll_mul: PllMul,
+    hpre: AhbPrescaler,
+    ppre1: ApbPrescaler,
+    ppre2: ApbPrescaler,
+    prediv: Prediv,
+    adc12_prescaler: Adc12Prescaler,
+    hse: Option<HseConfig>,
+}
+
+/// Builder for [`ClockConfig`].
+#[derive(Clone, Copy, Debug)]
+pub struct ClockConfigBuilder {
+    config: ClockConfig,
+    ppre1_explicit: bool,
+}
+
+/// Bus and timer clock frequencies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClockFrequencies {
+    /// System clock.
+    pub sysclk: u32,
+    /// AHB clock.
+    pub hclk: u32,
+    /// APB1 (low-speed) peripheral clock.
+    pub pclk1: u32,
+    /// APB2 (high-speed) peripheral clock.
+    pub pclk2: u32,
+    /// Clock of the timers on APB1.
+    pub tim_apb1: u32,
+    /// Clock of the timers on APB2.
+    pub tim_apb2: u32,
+}
+
+impl ClockFrequencies {
+    /// Derives the bus frequencies from `sysclk` and the prescalers.
+    ///
+    /// The timer clocks are twice the APB clock whenever the APB prescaler
+    /// divides.
+    pub fn new(
+        sysclk: u32,
+        hpre: AhbPrescaler,
+        ppre1: ApbPrescaler,
+        ppre2: ApbPrescaler,
+    ) -> Self {
+        let hclk = sysclk / hpre.divisor();
+        let pclk1 = hclk / ppre1.divisor();
+        let pclk2 = hclk / ppre2.divisor();
+        let tim = |pclk: u32, ppre: ApbPrescaler| {
+            if ppre == ApbPrescaler::Div1 {
+                pclk
+            } else {
+                pclk * 2
+            }
+        };
+        Self {
+            sysclk,
+            hclk,
+            pclk1,
+            pclk2,
+            tim_apb1: tim(pclk1, ppre1),
+            tim_apb2: tim(pclk2, ppre2),
+        }
+    }
+}
+
+impl ClockConfig {
+    /// Returns the reset configuration: SYSCLK from HSI, nothing divided.
+    pub const fn reset() -> Self {
+        Self {
+            sysclk_src: SysClkSrc::Hsi,
+            pll_src: PllSrc::HsiDiv2,
+            pll_mul: PllMul::Mul2,
+            hpre: AhbPrescaler::Div1,
+            ppre1: ApbPrescaler::Div1,
+            ppre2: ApbPrescaler::Div1,
+            prediv: Prediv::Div1,
+            adc12_prescaler: Adc12Prescaler::Off,
+            hse: None,
+        }
+    }
+
+    /// Creates a new [`ClockConfigBuilder`] starting from the reset
+    /// configuration.
+    #[inline]
+    pub fn builder() -> ClockConfigBuilder {
+        ClockConfigBuilder {
+            config: Self::reset(),
+            ppre1_explicit: false,
+        }
+    }
+
+    /// Returns the system clock source.
+    #[inline]
+    pub fn sysclk_src(&self) -> SysClkSrc {
+        self.sysclk_src
+    }
+
+    /// Returns the PLL entry clock source.
+    #[inline]
+    pub fn pll_src(&self) -> PllSrc {
+        self.pll_src
+    }
+
+    /// Returns the PLL multiplication factor.
+    #[inline]
+    pub fn pll_mul(&self) -> PllMul {
+        self.pll_mul
+    }
+
+    /// Returns the AHB prescaler.
+    #[inline]
+    pub fn hpre(&self) -> AhbPrescaler {
+        self.hpre
+    }
+
+    /// Returns the APB1 prescaler.
+    #[inline]
+    pub fn ppre1(&self) -> ApbPrescaler {
+        self.ppre1
+    }
+
+    /// Returns the APB2 prescaler.
+    #[inline]
+    pub fn ppre2(&self) -> ApbPrescaler {
+        self.ppre2
+    }
+
+    /// Returns the HSE division factor for the PLL input.
+    #[inline]
+    pub fn prediv(&self) -> Prediv {
+        self.prediv
+    }
+
+    /// Returns the ADC12 prescaler.
+    #[inline]
+    pub fn adc12_prescaler(&self) -> Adc12Prescaler {
+        self.adc12_prescaler
+    }
+
+    /// Returns the ADC12 clock frequency derived from the PLL, if enabled.
+    pub fn adc12_clk(&self) -> Option<u32> {
+        self.adc12_prescaler
+            .divisor()
+            .map(|divisor| self.pll_output() / divisor)
+    }
+
+    /// Returns the HSE description, if any.
+    #[inline]
+    pub fn hse(&self) -> Option<HseConfig> {
+        self.hse
+    }
+
+    /// Returns the HSE frequency, or 0 if no HSE is described.
+    #[inline]
+    pub fn hse_freq(&self) -> u32 {
+        self.hse.map_or(0, |hse| hse.freq)
+    }
+
+    /// Returns `true` if the configuration needs the HSE running.
+    pub fn uses_hse(&self) -> bool {
+        match self.sysclk_src {
+            SysClkSrc::Hse => true,
+            SysClkSrc::Pll => self.pll_src == PllSrc::HsePrediv,
+            SysClkSrc::Hsi => false,
+        }
+    }
+
+    /// Returns the PLL input frequency.
+    pub fn pll_input(&self) -> u32 {
+        match self.pll_src {
+            PllSrc::HsiDiv2 => HSI_CLK / 2,
+            PllSrc::HsePrediv => self.hse_freq() / self.prediv.divisor(),
+        }
+    }
+
+    /// Returns the PLL output frequency.
+    pub fn pll_output(&self) -> u32 {
+        self.pll_input() * self.pll_mul.factor()
+    }
+
+    /// Returns the SYSCLK frequency.
+    pub fn sysclk(&self) -> u32 {
+        match self.sysclk_src {
+            SysClkSrc::Hsi => HSI_CLK,
+            SysClkSrc::Hse => self.hse_freq(),
+            SysClkSrc::Pll => self.pll_output(),
+        }
+    }
+
+    /// Returns the AHB clock (HCLK) frequency.
+    pub fn hclk(&self) -> u32 {
+        self.sysclk() / self.hpre.divisor()
+    }
+
+    /// Returns the APB1 clock (PCLK1) frequency.
+    pub fn pclk1(&self) -> u32 {
+        self.hclk() / self.ppre1.divisor()
+    }
+
+    /// Returns the APB2 clock (PCLK2) frequency.
+    pub fn pclk2(&self) -> u32 {
+        self.hclk() / self.ppre2.divisor()
+    }
+
+    /// Returns all frequencies the configuration will produce.
+    pub fn frequencies(&self) -> ClockFrequencies {
+        ClockFrequencies::new(self.sysclk(), self.hpre, self.ppre1, self.ppre2)
+    }
+
+    fn validate(&self) -> Result<(), ClockConfigError> {
+        if self.uses_hse() {
+            let hse = self.hse.ok_or(ClockConfigError::HseMissing)?;
+            let min = match hse.mode {
+                HseMode::Oscillator => HSE_OSC_MIN,
+                HseMode::Bypass => HSE_BYPASS_MIN,
+            };
+            if hse.freq < min || hse.freq > HSE_MAX {
+                return Err(ClockConfigError::HseOutOfRange(hse.freq));
+            }
+        }
+        if self.sysclk_src == SysClkSrc::Pll {
+            let input = self.pll_input();
+            if input < PLL_IN_MIN || input > PLL_IN_MAX {
+                return Err(ClockConfigError::PllInputOutOfRange(input));
+            }
+            let output = self.pll_output();
+            if output < PLL_OUT_MIN || output > SYSCLK_MAX {
+                return Err(ClockConfigError::PllOutputOutOfRange(output));
+            }
+        }
+        let sysclk = self.sysclk();
+        if sysclk > SYSCLK_MAX {
+            return Err(ClockConfigError::SysclkTooHigh(sysclk));
+        }
+        let pclk1 = self.pclk1();
+        if pclk1 > PCLK1_MAX {
+            return Err(ClockConfigError::Pclk1TooHigh(pclk1));
+        }
+        let pclk2 = self.pclk2();
+        if pclk2 > PCLK2_MAX {
+            return Err(ClockConfigError::Pclk2TooHigh(pclk2));
+        }
+        Ok(())
+    }
+}
+
+impl Default for ClockConfig {
+    #[inline]
+    fn default() -> Self {
+        Self::reset()
+    }
+}
+
+impl ClockConfigBuilder {
+    /// Selects the system clock source.
+    #[inline]
+    pub fn sysclk_src(mut self, sysclk_src: SysClkSrc) -> Self {
+        self.config.sysclk_src = sysclk_src;
+        self
+    }
+
+    /// Selects the PLL entry clock source and multiplication factor.
+    #[inline]
+    pub fn pll(mut self, pll_src: PllSrc, pll_mul: PllMul) -> Self {
+        self.config.pll_src = pll_src;
+        self.config.pll_mul = pll_mul;
+        self
+    }
+
+    /// Describes the HSE source.
+    #[inline]
+    pub fn hse(mut self, mode: HseMode, freq: u32) -> Self {
+        self.config.hse = Some(HseConfig { mode, freq });
+        self
+    }
+
+    /// Selects the AHB prescaler.
+    #[inline]
+    pub fn hpre(mut self, hpre: AhbPrescaler) -> Self {
+        self.config.hpre = hpre;
+        self
+    }
+
+    /// Selects the APB1 prescaler.
+    ///
+    /// If not called, the smallest prescaler which keeps APB1 within 36 MHz
+    /// is selected.
+    #[inline]
+    pub fn ppre1(mut self, ppre1: ApbPrescaler) -> Self {
+        self.config.ppre1 = ppre1;
+        self.ppre1_explicit = true;
+        self
+    }
+
+    /// Selects the APB2 prescaler.
+    #[inline]
+    pub fn ppre2(mut self, ppre2: ApbPrescaler) -> Self {
+        self.config.ppre2 = ppre2;
+        self
+    }
+
+    /// Selects the HSE division factor for the PLL input.
+    #[inline]
+    pub fn prediv(mut self, prediv: Prediv) -> Self {
+        self.config.prediv = prediv;
+        self
+    }
+
+    /// Returns the configuration without validating it.
+    ///
+    /// Useful to compute the frequencies of a candidate configuration.
+    #[inline]
+    pub(crate) fn build_unchecked(self) -> ClockConfig {
+        self.resolve()
+    }
+
+    /// Selects the ADC12 prescaler.
+    #[inline]
+    pub fn adc12_prescaler(mut self, adc12_prescaler: Adc12Prescaler) -> Self {
+        self.config.adc12_prescaler = adc12_prescaler;
+        self
+    }
+
+    /// Validates the configuration and returns it.
+    pub fn build(self) -> Result<ClockConfig, ClockConfigError> {
+        let config = self.resolve();
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn resolve(self) -> ClockConfig {
+        let mut config = self.config;
+        if !self.ppre1_explicit {
+            if let Some(ppre1) = ApbPrescaler::for_max(config.hclk(), PCLK1_MAX) {
+                config.ppre1 = ppre1;
+            }
+        }
+        config
+    }
+}
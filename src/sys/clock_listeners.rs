@@ -0,0 +1,93 @@
+//! Clock change notifications.
+//!
+//! Subsystems which depend on a bus clock register a [`ClockListener`] once.
+//! It is called right before and right after every clock switch, so new
+//! peripherals don't need to touch the clock switching code. A listener owns
+//! the register tokens and state it needs.
+
+use crate::sys::{clock_config::ClockFrequencies, system::SysTick};
+use alloc::{boxed::Box, vec::Vec};
+use core::cell::RefCell;
+use drone_core::log;
+use drone_cortexm::{reg::prelude::*, swo};
+
+/// When a listener is called.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockPhase {
+    /// The clocks are still running at `old`.
+    Before,
+    /// The clocks are running at `new`.
+    After,
+}
+
+/// A clock switch.
+#[derive(Clone, Copy, Debug)]
+pub struct ClockChange {
+    /// Phase of the switch.
+    pub phase: ClockPhase,
+    /// Frequencies before the switch.
+    pub old: ClockFrequencies,
+    /// Frequencies after the switch. In [`ClockPhase::Before`] these are the
+    /// requested ones, in [`ClockPhase::After`] the achieved ones.
+    pub new: ClockFrequencies,
+}
+
+/// A callback notified about clock switches.
+pub type ClockListener = Box<dyn FnMut(&ClockChange) + Send>;
+
+/// Registry of clock listeners.
+pub struct ClockListeners {
+    listeners: RefCell<Vec<ClockListener>>,
+}
+
+impl ClockListeners {
+    /// Creates an empty registry.
+    #[inline]
+    pub fn new() -> Self {
+        Self { listeners: RefCell::new(Vec::new()) }
+    }
+
+    /// Adds `listener` to the registry.
+    pub fn register(&mut self, listener: impl FnMut(&ClockChange) + Send + 'static) {
+        self.listeners.get_mut().push(Box::new(listener));
+    }
+
+    /// Calls all listeners in registration order.
+    ///
+    /// # Panics
+    ///
+    /// If called from within a listener.
+    pub fn notify(&self, change: &ClockChange) {
+        for listener in self.listeners.borrow_mut().iter_mut() {
+            listener(change);
+        }
+    }
+}
+
+impl Default for ClockListeners {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Keeps the SWO baud rate in line with HCLK.
+pub fn swo_listener(change: &ClockChange) {
+    match change.phase {
+        ClockPhase::Before => swo::flush(),
+        ClockPhase::After => swo::update_prescaler(change.new.hclk / log::baud_rate!() - 1),
+    }
+}
+
+/// Returns a listener which rescales the reload value of a running SysTick,
+/// so its period survives the switch.
+pub fn sys_tick_listener(sys_tick: SysTick) -> impl FnMut(&ClockChange) + Send {
+    move |change| {
+        if change.phase != ClockPhase::After || change.old.hclk == change.new.hclk {
+            return;
+        }
+        let reload = u64::from(sys_tick.stk_load.load().reload());
+        let reload = reload * u64::from(change.new.hclk) / u64::from(change.old.hclk);
+        sys_tick.stk_load.store(|r| r.write_reload(reload.min(0xFF_FFFF) as u32));
+        sys_tick.stk_val.store(|r| r.write_current(0));
+    }
+}
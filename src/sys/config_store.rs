@@ -0,0 +1,538 @@
+//! Key-value configuration store in internal flash.
+//!
+//! The store lives in the last two flash pages, which are excluded from the
+//! `[memory.flash]` region in `Drone.toml`. One page is active at a time and
+//! is written as an append-only log of records:
+//!
+//! ```text
+//! page:   | magic | seq | record | record | ... | 0xFFFF ...
+//! record: | key | len | data (len bytes, padded) | crc |
+//! ```
+//!
+//! The latest valid record of a key wins, a record with an empty value
+//! removes the key. When the active page is full, the live records are
+//! copied to the other page, which then becomes active with the next
+//! sequence number.
+//!
+//! Power-loss safety relies on the write order. A record is valid only
+//! once its CRC is written last, and a page only once its magic is written
+//! after everything else. Left-over partial records are skipped, and if
+//! both pages are valid, the one with the newer sequence number is used.
+//!
+//! The flash is accessed through [`ConfigFlash`], so the store runs on the
+//! host against [`RamFlash`] as well.
+
+use crate::drv::flash::{Flash, FlashError, FLASH_BASE, FLASH_PAGE_SIZE, FLASH_SIZE};
+use core::ptr;
+
+/// Address of the first store page.
+pub const STORE_BASE: u32 = FLASH_BASE + FLASH_SIZE - 2 * FLASH_PAGE_SIZE;
+/// Largest value in bytes.
+pub const MAX_VALUE_LEN: usize = 64;
+
+/// Well-known keys.
+pub mod keys {
+    /// The selected clock mode.
+    pub const CLOCK_MODE: u16 = 1;
+    /// LED on/off interval in ticks at 4 MHz HCLK.
+    pub const LED_TIMING: u16 = 2;
+    /// HSI trimming value found by the calibration.
+    pub const HSI_TRIM: u16 = 3;
+}
+
+const PAGE_MAGIC: u16 = 0x5AC3;
+const HEADER_LEN: u32 = 4;
+const ERASED: u16 = 0xFFFF;
+
+/// Flash operations used by the store.
+pub trait ConfigFlash {
+    /// Reads the half-word at `addr`.
+    fn read_half_word(&self, addr: u32) -> u16;
+
+    /// Programs the erased half-word at `addr`.
+    fn program_half_word(&self, addr: u32, value: u16) -> Result<(), FlashError>;
+
+    /// Erases the page starting at `addr`.
+    fn erase_page(&self, addr: u32) -> Result<(), FlashError>;
+}
+
+impl ConfigFlash for Flash {
+    fn read_half_word(&self, addr: u32) -> u16 {
+        unsafe { ptr::read_volatile(addr as *const u16) }
+    }
+
+    fn program_half_word(&self, addr: u32, value: u16) -> Result<(), FlashError> {
+        self.unlock()?;
+        let result = Flash::program_half_word(self, addr, value);
+        self.lock();
+        result
+    }
+
+    fn erase_page(&self, addr: u32) -> Result<(), FlashError> {
+        self.unlock()?;
+        let result = Flash::erase_page(self, addr);
+        self.lock();
+        result
+    }
+}
+
+impl<T: ConfigFlash> ConfigFlash for &T {
+    fn read_half_word(&self, addr: u32) -> u16 {
+        (**self).read_half_word(addr)
+    }
+
+    fn program_half_word(&self, addr: u32, value: u16) -> Result<(), FlashError> {
+        (**self).program_half_word(addr, value)
+    }
+
+    fn erase_page(&self, addr: u32) -> Result<(), FlashError> {
+        (**self).erase_page(addr)
+    }
+}
+
+/// Configuration store error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StoreError {
+    /// The flash operation failed.
+    Flash(FlashError),
+    /// The live records don't fit in a page.
+    Full,
+    /// The key is reserved.
+    InvalidKey,
+    /// The value is longer than [`MAX_VALUE_LEN`].
+    TooLong,
+    /// [`ConfigStore::mount`] wasn't called.
+    NotMounted,
+}
+
+impl From<FlashError> for StoreError {
+    fn from(err: FlashError) -> Self {
+        StoreError::Flash(err)
+    }
+}
+
+/// A record found in the log.
+#[derive(Clone, Copy)]
+struct Record {
+    addr: u32,
+    key: u16,
+    len: u16,
+}
+
+impl Record {
+    fn data_addr(&self) -> u32 {
+        self.addr + 4
+    }
+
+    fn size(&self) -> u32 {
+        record_size(self.len as usize)
+    }
+}
+
+/// Key-value configuration store.
+pub struct ConfigStore<F: ConfigFlash> {
+    flash: F,
+    base: u32,
+    active: Option<u32>,
+    seq: u16,
+    tail: u32,
+}
+
+impl<F: ConfigFlash> ConfigStore<F> {
+    /// Creates a store in the reserved pages at [`STORE_BASE`].
+    pub fn new(flash: F) -> Self {
+        Self::with_base(flash, STORE_BASE)
+    }
+
+    /// Creates a store in the two pages starting at `base`.
+    pub fn with_base(flash: F, base: u32) -> Self {
+        Self { flash, base, active: None, seq: 0, tail: 0 }
+    }
+
+    /// Releases the flash.
+    pub fn free(self) -> F {
+        self.flash
+    }
+
+    /// Finds the active page, formatting the store if there is none.
+    pub fn mount(&mut self) -> Result<(), StoreError> {
+        let pages = [self.base, self.base + FLASH_PAGE_SIZE];
+        let mut active: Option<(u32, u16)> = None;
+        for &page in &pages {
+            if self.flash.read_half_word(page) != PAGE_MAGIC {
+                continue;
+            }
+            let seq = self.flash.read_half_word(page + 2);
+            let newer = match active {
+                None => true,
+                // Sequence numbers wrap around.
+                Some((_, other)) => (seq.wrapping_sub(other) as i16) > 0,
+            };
+            if newer {
+                active = Some((page, seq));
+            }
+        }
+        match active {
+            Some((page, seq)) => {
+                self.active = Some(page);
+                self.seq = seq;
+                self.tail = self.scan_tail(page);
+            }
+            None => {
+                self.flash.erase_page(pages[0])?;
+                self.flash.erase_page(pages[1])?;
+                self.activate(pages[0], 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies the value of `key` into `buf`, and returns its length, or
+    /// `None` if the key isn't set. `buf` should hold [`MAX_VALUE_LEN`]
+    /// bytes, the value is truncated otherwise.
+    pub fn read(&self, key: u16, buf: &mut [u8]) -> Result<Option<usize>, StoreError> {
+        let page = self.active.ok_or(StoreError::NotMounted)?;
+        match self.find(page, key) {
+            Some(record) if record.len > 0 => {
+                let len = (record.len as usize).min(buf.len());
+                for (i, byte) in buf[..len].iter_mut().enumerate() {
+                    let half_word = self.flash.read_half_word(record.data_addr() + (i as u32 & !1));
+                    *byte = half_word.to_le_bytes()[i % 2];
+                }
+                Ok(Some(record.len as usize))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Sets `key` to `value`.
+    pub fn write(&mut self, key: u16, value: &[u8]) -> Result<(), StoreError> {
+        if key == ERASED {
+            return Err(StoreError::InvalidKey);
+        }
+        if value.len() > MAX_VALUE_LEN {
+            return Err(StoreError::TooLong);
+        }
+        let page = self.active.ok_or(StoreError::NotMounted)?;
+        let size = record_size(value.len());
+        if self.tail + size > page + FLASH_PAGE_SIZE {
+            self.compact()?;
+            let page = self.active.ok_or(StoreError::NotMounted)?;
+            if self.tail + size > page + FLASH_PAGE_SIZE {
+                return Err(StoreError::Full);
+            }
+        }
+        let addr = self.tail;
+        // Consider the space taken even if the write fails halfway.
+        self.tail += size;
+        self.append(addr, key, value)
+    }
+
+    /// Removes `key`.
+    pub fn remove(&mut self, key: u16) -> Result<(), StoreError> {
+        let page = self.active.ok_or(StoreError::NotMounted)?;
+        if self.find(page, key).map_or(true, |record| record.len == 0) {
+            return Ok(());
+        }
+        self.write(key, &[])
+    }
+
+    /// Reads a `u32` value.
+    pub fn read_u32(&self, key: u16) -> Result<Option<u32>, StoreError> {
+        let mut buf = [0; 4];
+        Ok(match self.read(key, &mut buf)? {
+            Some(4) => Some(u32::from_le_bytes(buf)),
+            _ => None,
+        })
+    }
+
+    /// Writes a `u32` value.
+    pub fn write_u32(&mut self, key: u16, value: u32) -> Result<(), StoreError> {
+        self.write(key, &value.to_le_bytes())
+    }
+
+    /// Moves the live records to the other page and makes it active.
+    pub fn compact(&mut self) -> Result<(), StoreError> {
+        let page = self.active.ok_or(StoreError::NotMounted)?;
+        let other = if page == self.base { self.base + FLASH_PAGE_SIZE } else { self.base };
+        self.flash.erase_page(other)?;
+        let mut dst = other + HEADER_LEN;
+        let mut src = page + HEADER_LEN;
+        while let Some((record, next)) = self.next_record(page, src) {
+            src = next;
+            let record = match record {
+                Some(record) => record,
+                None => continue,
+            };
+            let latest = self.find(page, record.key).map(|latest| latest.addr);
+            if latest != Some(record.addr) || record.len == 0 {
+                continue;
+            }
+            // Copy verbatim, CRC included.
+            for offset in (0..record.size()).step_by(2) {
+                let half_word = self.flash.read_half_word(record.addr + offset);
+                self.flash.program_half_word(dst + offset, half_word)?;
+            }
+            dst += record.size();
+        }
+        self.activate(other, self.seq.wrapping_add(1))?;
+        self.tail = dst;
+        // The old page is dead now, erase it for the next compaction.
+        self.flash.erase_page(page)?;
+        Ok(())
+    }
+
+    /// Writes the header of `page`, the magic last.
+    fn activate(&mut self, page: u32, seq: u16) -> Result<(), StoreError> {
+        self.flash.program_half_word(page + 2, seq)?;
+        self.flash.program_half_word(page, PAGE_MAGIC)?;
+        self.active = Some(page);
+        self.seq = seq;
+        self.tail = page + HEADER_LEN;
+        Ok(())
+    }
+
+    /// Writes a record at `addr`, the CRC last.
+    fn append(&self, addr: u32, key: u16, value: &[u8]) -> Result<(), StoreError> {
+        self.flash.program_half_word(addr, key)?;
+        self.flash.program_half_word(addr + 2, value.len() as u16)?;
+        let mut data = addr + 4;
+        for chunk in value.chunks(2) {
+            let half_word = u16::from_le_bytes([chunk[0], *chunk.get(1).unwrap_or(&0xFF)]);
+            self.flash.program_half_word(data, half_word)?;
+            data += 2;
+        }
+        self.flash.program_half_word(data, crc16(key, value))?;
+        Ok(())
+    }
+
+    /// Returns the latest valid record of `key`.
+    fn find(&self, page: u32, key: u16) -> Option<Record> {
+        let mut found = None;
+        let mut addr = page + HEADER_LEN;
+        while let Some((record, next)) = self.next_record(page, addr) {
+            if let Some(record) = record {
+                if record.key == key {
+                    found = Some(record);
+                }
+            }
+            addr = next;
+        }
+        found
+    }
+
+    /// Returns the address past the last record.
+    fn scan_tail(&self, page: u32) -> u32 {
+        let mut addr = page + HEADER_LEN;
+        while let Some((_, next)) = self.next_record(page, addr) {
+            addr = next;
+        }
+        addr
+    }
+
+    /// Parses the record at `addr`. Returns `None` at the end of the log,
+    /// otherwise the record if it is valid, and the address of the next one.
+    ///
+    /// A record with an unreadable length ends the log, and the rest of the
+    /// page is considered taken.
+    fn next_record(&self, page: u32, addr: u32) -> Option<(Option<Record>, u32)> {
+        let end = page + FLASH_PAGE_SIZE;
+        if addr + 2 > end {
+            return None;
+        }
+        let key = self.flash.read_half_word(addr);
+        if key == ERASED {
+            return None;
+        }
+        let len = self.flash.read_half_word(addr + 2);
+        if len as usize > MAX_VALUE_LEN || addr + record_size(len as usize) > end {
+            return Some((None, end));
+        }
+        let record = Record { addr, key, len };
+        let mut buf = [0; MAX_VALUE_LEN];
+        for (i, byte) in buf[..len as usize].iter_mut().enumerate() {
+            let half_word = self.flash.read_half_word(record.data_addr() + (i as u32 & !1));
+            *byte = half_word.to_le_bytes()[i % 2];
+        }
+        let crc_addr = addr + record.size() - 2;
+        let valid = self.flash.read_half_word(crc_addr) == crc16(key, &buf[..len as usize]);
+        Some((if valid { Some(record) } else { None }, addr + record.size()))
+    }
+}
+
+/// Returns the size of a record with a `len` bytes value.
+fn record_size(len: usize) -> u32 {
+    4 + ((len as u32 + 1) & !1) + 2
+}
+
+/// CRC-16/CCITT-FALSE over the key, the length and the value.
+fn crc16(key: u16, value: &[u8]) -> u16 {
+    let header = [key.to_le_bytes(), (value.len() as u16).to_le_bytes()];
+    header.iter().flatten().chain(value).fold(0xFFFF, |mut crc, &byte| {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+/// An in-memory model of the two store pages, with the programming rules of
+/// the real flash, to run the store on the host.
+#[cfg(feature = "std")]
+pub struct RamFlash {
+    mem: core::cell::RefCell<[u16; 2 * PAGE_HALF_WORDS]>,
+    base: u32,
+}
+
+#[cfg(feature = "std")]
+const PAGE_HALF_WORDS: usize = FLASH_PAGE_SIZE as usize / 2;
+
+#[cfg(feature = "std")]
+impl RamFlash {
+    /// Creates an erased model of the two pages starting at `base`.
+    pub fn new(base: u32) -> Self {
+        Self { mem: core::cell::RefCell::new([ERASED; 2 * PAGE_HALF_WORDS]), base }
+    }
+
+    fn index(&self, addr: u32) -> Result<usize, FlashError> {
+        if addr % 2 != 0 || addr < self.base || addr >= self.base + 2 * FLASH_PAGE_SIZE {
+            Err(FlashError::Address)
+        } else {
+            Ok(((addr - self.base) / 2) as usize)
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl ConfigFlash for RamFlash {
+    fn read_half_word(&self, addr: u32) -> u16 {
+        self.index(addr).map_or(ERASED, |i| self.mem.borrow()[i])
+    }
+
+    fn program_half_word(&self, addr: u32, value: u16) -> Result<(), FlashError> {
+        let i = self.index(addr)?;
+        let mut mem = self.mem.borrow_mut();
+        if mem[i] != ERASED {
+            return Err(FlashError::Programming);
+        }
+        mem[i] = value;
+        Ok(())
+    }
+
+    fn erase_page(&self, addr: u32) -> Result<(), FlashError> {
+        let start = self.index(addr)? / PAGE_HALF_WORDS * PAGE_HALF_WORDS;
+        for half_word in &mut self.mem.borrow_mut()[start..start + PAGE_HALF_WORDS] {
+            *half_word = ERASED;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    /// Writes a record at `addr` by hand, with the given `crc`.
+    fn raw_record(flash: &RamFlash, addr: u32, key: u16, value: &[u8], crc: u16) {
+        flash.program_half_word(addr, key).unwrap();
+        flash.program_half_word(addr + 2, value.len() as u16).unwrap();
+        for (i, chunk) in value.chunks(2).enumerate() {
+            let half_word = u16::from_le_bytes([chunk[0], *chunk.get(1).unwrap_or(&0xFF)]);
+            flash.program_half_word(addr + 4 + i as u32 * 2, half_word).unwrap();
+        }
+        flash.program_half_word(addr + record_size(value.len()) - 2, crc).unwrap();
+    }
+
+    fn mounted(flash: &RamFlash) -> ConfigStore<&RamFlash> {
+        let mut store = ConfigStore::new(flash);
+        store.mount().unwrap();
+        store
+    }
+
+    #[test]
+    fn torn_record_is_skipped() {
+        let flash = RamFlash::new(STORE_BASE);
+        let mut store = mounted(&flash);
+        store.write_u32(keys::LED_TIMING, 100).unwrap();
+        // Power lost after the first data half-word of the next record.
+        let torn = store.tail;
+        flash.program_half_word(torn, keys::LED_TIMING).unwrap();
+        flash.program_half_word(torn + 2, 4).unwrap();
+        flash.program_half_word(torn + 4, 200).unwrap();
+
+        let mut store = mounted(&flash);
+        assert_eq!(store.read_u32(keys::LED_TIMING), Ok(Some(100)));
+        assert_eq!(store.tail, torn + record_size(4));
+        store.write_u32(keys::LED_TIMING, 300).unwrap();
+        assert_eq!(mounted(&flash).read_u32(keys::LED_TIMING), Ok(Some(300)));
+    }
+
+    #[test]
+    fn bad_crc_is_rejected() {
+        let flash = RamFlash::new(STORE_BASE);
+        let mut store = mounted(&flash);
+        store.write_u32(keys::HSI_TRIM, 17).unwrap();
+        let value = 18u32.to_le_bytes();
+        let crc = crc16(keys::HSI_TRIM, &value) ^ 1;
+        raw_record(&flash, store.tail, keys::HSI_TRIM, &value, crc);
+
+        let mut store = mounted(&flash);
+        assert_eq!(store.read_u32(keys::HSI_TRIM), Ok(Some(17)));
+        // The log goes on past the rejected record.
+        store.write_u32(keys::HSI_TRIM, 19).unwrap();
+        assert_eq!(mounted(&flash).read_u32(keys::HSI_TRIM), Ok(Some(19)));
+    }
+
+    #[test]
+    fn full_page_is_compacted() {
+        let flash = RamFlash::new(STORE_BASE);
+        let mut store = mounted(&flash);
+        store.write_u32(keys::HSI_TRIM, 17).unwrap();
+        store.write_u32(keys::LED_TIMING, 0).unwrap();
+        store.remove(keys::LED_TIMING).unwrap();
+        let first = store.active;
+        let mut value = 0;
+        while store.active == first {
+            value += 1;
+            store.write_u32(keys::CLOCK_MODE, value).unwrap();
+        }
+        let page = store.active.unwrap();
+        assert_eq!(page, STORE_BASE + FLASH_PAGE_SIZE);
+        assert_eq!(store.seq, 1);
+        // The HSI trim and the previous clock mode were copied, the removed
+        // LED timing was dropped, then the last clock mode was appended.
+        assert_eq!(store.tail, page + HEADER_LEN + 3 * record_size(4));
+        assert_eq!(flash.read_half_word(STORE_BASE), ERASED);
+
+        let store = mounted(&flash);
+        assert_eq!(store.active, Some(page));
+        assert_eq!(store.read_u32(keys::HSI_TRIM), Ok(Some(17)));
+        assert_eq!(store.read_u32(keys::LED_TIMING), Ok(None));
+        assert_eq!(store.read_u32(keys::CLOCK_MODE), Ok(Some(value)));
+    }
+
+    #[test]
+    fn sequence_number_wraps_around() {
+        let flash = RamFlash::new(STORE_BASE);
+        let other = STORE_BASE + FLASH_PAGE_SIZE;
+        flash.program_half_word(STORE_BASE + 2, 0xFFFF).unwrap();
+        flash.program_half_word(STORE_BASE, PAGE_MAGIC).unwrap();
+        let value = 1u32.to_le_bytes();
+        let crc = crc16(keys::HSI_TRIM, &value);
+        raw_record(&flash, STORE_BASE + HEADER_LEN, keys::HSI_TRIM, &value, crc);
+        // Both pages valid, as when power is lost before a compaction erased
+        // the old page. Sequence number 0 follows 0xFFFF.
+        flash.program_half_word(other + 2, 0).unwrap();
+        flash.program_half_word(other, PAGE_MAGIC).unwrap();
+
+        let mut store = mounted(&flash);
+        assert_eq!(store.active, Some(other));
+        assert_eq!(store.seq, 0);
+        assert_eq!(store.read_u32(keys::HSI_TRIM), Ok(None));
+
+        store.compact().unwrap();
+        assert_eq!(store.active, Some(STORE_BASE));
+        assert_eq!(store.seq, 1);
+    }
+}
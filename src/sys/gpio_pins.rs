@@ -1,91 +1,182 @@
 //! GPIO pins bindings.
 
-use crate::drv::gpio::GpioHeadEn;
+/// Declares a set of GPIO pins across ports A, B and F.
+///
+/// Generates the pin set struct with a typed public field per pin, its
+/// resource struct, and a macro acquiring the resource from `Regs`. The
+/// resource struct has to be in scope where the macro is used. The pin set's
+/// `init` takes the head enable tokens of all ports used, see
+/// [`HeadTokens`](crate::drv::gpio_pin::HeadTokens).
+///
+/// ```ignore
+/// gpio_pins! {
+///     /// Application pins.
+///     pub struct Pins;
+///     /// Resource of [`Pins`].
+///     pub struct PinsRes;
+///     macro drv_pins;
+///
+///     /// Status LED.
+///     led: GpioA8 = PushPullOutput { pull: None, speed: Low, level: High },
+///     /// USART2 TX to the ST-LINK virtual COM port.
+///     tx: GpioA2 = Alternate<7> { output: PushPull, speed: Low, pull: None },
+///     /// Battery voltage sense.
+///     sense: GpioA0 = Analog {},
+/// }
+/// ```
+///
+/// The modes and their settings are:
+///
+/// * `PushPullOutput { pull, speed, level }` and
+///   `OpenDrainOutput { pull, speed, level }`,
+/// * `Input { pull }`,
+/// * `Analog {}`,
+/// * `Alternate<AF> { output, speed, pull }`, where `AF` is checked at
+///   compile time against the functions of the pin, see
+///   [`PinAf`](crate::drv::gpio_pin::PinAf).
+#[macro_export]
+macro_rules! gpio_pins {
+    (@acquire ($d:tt) $acquire:ident $res:ident { $($pin:ident: $ty:ident),* }) => {
+        macro_rules! $acquire {
+            ($d reg:ident) => {
+                $res { $($pin: $crate::gpio_pin_periph!($d reg, $ty)),* }
+            };
+        }
+    };
 
-use drone_core::inventory;
-use drone_cortexm::reg::prelude::*;
-use drone_stm32_map::periph::gpio::{
-    head::GpioBHead,
-    pin::{GpioB4, GpioB5, GpioPinPeriph},
-};
+    (@init $pin:expr, $token:expr, $ty:ident, PushPullOutput {
+        pull: $pull:ident, speed: $speed:ident, level: $level:ident $(,)?
+    }) => {
+        $pin.into_configured::<$crate::drv::gpio_pin::PushPullOutput>(
+            $token,
+            $crate::gpio_pins!(@config $pull, $speed, $level),
+        )
+    };
 
-/// Acquires [`GpioPins`].
-#[doc(hidden)]
-#[macro_export]
-macro_rules! drv_gpio_pins {
-    ($reg:ident) => {
-        $crate::sys::gpio_pins::GpioPins::new($crate::sys::gpio_pins::GpioPinsRes {
-            gpio_b4: ::drone_stm32_map::periph::gpio::periph_gpio_b4!($reg),
-            gpio_b5: ::drone_stm32_map::periph::gpio::periph_gpio_b5!($reg),
-        })
+    (@init $pin:expr, $token:expr, $ty:ident, OpenDrainOutput {
+        pull: $pull:ident, speed: $speed:ident, level: $level:ident $(,)?
+    }) => {
+        $pin.into_configured::<$crate::drv::gpio_pin::OpenDrainOutput>(
+            $token,
+            $crate::gpio_pins!(@config $pull, $speed, $level),
+        )
     };
-}
 
-/// GPIO pins driver.
-pub struct GpioPins(GpioPinsRes);
+    (@init $pin:expr, $token:expr, $ty:ident, Input { pull: $pull:ident $(,)? }) => {
+        $pin.into_input($token, $crate::drv::gpio_pin::Pull::$pull)
+    };
 
-/// GPIO pins resource for driving the LEDs on the NUCLEO.
-pub struct GpioPinsRes {
-    /// LED.
-    pub gpio_b4: GpioPinPeriph<GpioB4>,
-    /// Virtual user button.
-    pub gpio_b5: GpioPinPeriph<GpioB5>,
-}
+    (@init $pin:expr, $token:expr, $ty:ident, Analog {}) => {
+        $pin.into_analog($token)
+    };
 
-impl GpioPins {
-    /// Creates a new [`GpioPins`].
-    #[inline]
-    pub fn new(res: GpioPinsRes) -> Self {
-        Self(res)
-    }
+    (@init $pin:expr, $token:expr, $ty:ident, Alternate<$af:literal> {
+        output: $output:ident, speed: $speed:ident, pull: $pull:ident $(,)?
+    }) => {{
+        const _: () = assert!(
+            <::drone_stm32_map::periph::gpio::pin::$ty as $crate::drv::gpio_pin::PinAf>::AF_MASK
+                & 1 << $af
+                != 0,
+            "the alternate function isn't connected to the pin",
+        );
+        match $pin.into_alternate($token, $af, $crate::drv::gpio_pin::AfConfig {
+            output: $crate::drv::gpio_pin::OutputType::$output,
+            speed: $crate::drv::gpio_pin::Speed::$speed,
+            pull: $crate::drv::gpio_pin::Pull::$pull,
+        }) {
+            Ok(pin) => pin,
+            Err(_) => unreachable!(),
+        }
+    }};
 
-    /// Releases resources.
-    #[inline]
-    pub fn free(self) -> GpioPinsRes {
-        self.0
-    }
+    (@config $pull:ident, $speed:ident, $level:ident) => {
+        $crate::drv::gpio_pin::PinConfig {
+            pull: $crate::drv::gpio_pin::Pull::$pull,
+            speed: $crate::drv::gpio_pin::Speed::$speed,
+            level: $crate::drv::gpio_pin::Level::$level,
+        }
+    };
 
-    /// Initializes GPIO pins.
-    pub fn init(
-        &self,
-        _gpio_b_en: &inventory::Token<GpioHeadEn<GpioBHead>>,
-    ) {
-        self.0.gpio_b4.gpio_moder_moder.modify(|r| {
-            self.0.gpio_b4.gpio_moder_moder.write(r, 0b01);
-        });
-        self.0.gpio_b4.gpio_otyper_ot.modify(|r| {
-            self.0.gpio_b4.gpio_otyper_ot.clear(r);
-        });
-        self.0.gpio_b4.gpio_ospeedr_ospeedr.modify(|r| {
-            self.0.gpio_b4.gpio_ospeedr_ospeedr.write(r, 0b00);
-        });
-        self.0.gpio_b4.gpio_pupdr_pupdr.modify(|r| {
-            self.0.gpio_b4.gpio_pupdr_pupdr.write(r, 0b00);
-        });
-        // -------------
-        self.0.gpio_b5.gpio_moder_moder.modify(|r| {
-            self.0.gpio_b5.gpio_moder_moder.write(r, 0b00); // Input
-        });
-        self.0.gpio_b5.gpio_pupdr_pupdr.modify(|r| {
-            self.0.gpio_b5.gpio_pupdr_pupdr.write(r, 0b10);
-        });
-    }
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident;
+        $(#[$res_attr:meta])*
+        $res_vis:vis struct $res:ident;
+        macro $acquire:ident;
 
-    /// Sets the output `value` for the `pin`.
-    pub fn output(
-        &self,
-        pin: u8,
-        value: bool,
-    ) {
-        match pin {
-            1 => {
-                if value {
-                    self.0.gpio_b4.gpio_bsrr_bs.set_bit();
-                } else {
-                    self.0.gpio_b4.gpio_bsrr_br.set_bit();
+        $(
+            $(#[$pin_attr:meta])*
+            $pin:ident: $ty:ident = $mode:ident $(<$af:literal>)? { $($cfg:tt)* }
+        ),* $(,)?
+    ) => {
+        $(#[$attr])*
+        $vis struct $name {
+            $(
+                $(#[$pin_attr])*
+                pub $pin: $crate::drv::gpio_pin::GpioPin<
+                    ::drone_stm32_map::periph::gpio::pin::$ty,
+                    $crate::drv::gpio_pin::$mode,
+                >,
+            )*
+        }
+
+        $(#[$res_attr])*
+        $res_vis struct $res {
+            $(
+                $(#[$pin_attr])*
+                pub $pin: ::drone_stm32_map::periph::gpio::pin::GpioPinPeriph<
+                    ::drone_stm32_map::periph::gpio::pin::$ty,
+                >,
+            )*
+        }
+
+        impl $name {
+            /// Initializes the pins.
+            pub fn init<T: ?Sized>(res: $res, tokens: &T) -> Self
+            where
+                $(
+                    T: $crate::drv::gpio_pin::HeadTokens<
+                        <::drone_stm32_map::periph::gpio::pin::$ty
+                            as $crate::drv::gpio_pin::PinHead>::Head,
+                    >,
+                )*
+            {
+                Self {
+                    $(
+                        $pin: $crate::gpio_pins!(
+                            @init
+                            $crate::drv::gpio_pin::GpioPin::new(res.$pin),
+                            $crate::drv::gpio_pin::HeadTokens::<
+                                <::drone_stm32_map::periph::gpio::pin::$ty
+                                    as $crate::drv::gpio_pin::PinHead>::Head,
+                            >::head_token(tokens),
+                            $ty,
+                            $mode $(<$af>)? { $($cfg)* }
+                        ),
+                    )*
                 }
             }
-            _ => panic!("invalid gpio pin"),
+
+            /// Releases resources.
+            #[inline]
+            pub fn free(self) -> $res {
+                $res { $($pin: self.$pin.free()),* }
+            }
         }
-    }
+
+        $crate::gpio_pins!(@acquire ($) $acquire $res { $($pin: $ty),* });
+    };
+}
+
+gpio_pins! {
+    /// GPIO pins driver.
+    pub struct GpioPins;
+    /// GPIO pins resource for driving the LEDs on the NUCLEO.
+    pub struct GpioPinsRes;
+    macro drv_gpio_pins;
+
+    /// LED on D12.
+    led: GpioB4 = PushPullOutput { pull: None, speed: Low, level: Low },
+    /// Virtual user button on D11.
+    button: GpioB5 = Input { pull: Down },
 }
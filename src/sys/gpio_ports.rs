@@ -0,0 +1,54 @@
+//! GPIO ports bindings.
+
+use crate::drv::gpio::GpioHead;
+use drone_stm32_map::periph::gpio::head::{GpioAHead, GpioBHead, GpioFHead, GpioHeadPeriph};
+
+/// Acquires [`GpioPorts`].
+#[doc(hidden)]
+#[macro_export]
+macro_rules! drv_gpio_ports {
+    ($reg:ident) => {
+        $crate::sys::gpio_ports::GpioPorts::new(
+            ::drone_stm32_map::periph::gpio::periph_gpio_a_head!($reg),
+            ::drone_stm32_map::periph::gpio::periph_gpio_b_head!($reg),
+            ::drone_stm32_map::periph::gpio::periph_gpio_f_head!($reg),
+        )
+    };
+}
+
+/// GPIO ports driver for the ports of the LQFP32 package.
+///
+/// Nothing is clocked by this struct itself. Each port is enabled on its own
+/// through its field, e.g. `ports.b.enable()`, which turns the port clock on
+/// and returns a guard. The clock is turned off when the guard is dropped.
+/// Pins are configured with the guard's inventory token. A port can be reset
+/// through [`DrvRcc::reset`](crate::drv::common::DrvRcc::reset) while it
+/// isn't enabled.
+pub struct GpioPorts {
+    /// Port A.
+    pub a: GpioHead<GpioAHead>,
+    /// Port B.
+    pub b: GpioHead<GpioBHead>,
+    /// Port F.
+    pub f: GpioHead<GpioFHead>,
+}
+
+impl GpioPorts {
+    /// Creates a new [`GpioPorts`].
+    #[inline]
+    pub fn new(
+        a: GpioHeadPeriph<GpioAHead>,
+        b: GpioHeadPeriph<GpioBHead>,
+        f: GpioHeadPeriph<GpioFHead>,
+    ) -> Self {
+        Self { a: GpioHead::new(a), b: GpioHead::new(b), f: GpioHead::new(f) }
+    }
+
+    /// Releases the peripherals.
+    #[inline]
+    pub fn free(
+        self,
+    ) -> (GpioHeadPeriph<GpioAHead>, GpioHeadPeriph<GpioBHead>, GpioHeadPeriph<GpioFHead>) {
+        (self.a.free(), self.b.free(), self.f.free())
+    }
+}
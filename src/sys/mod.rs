@@ -1,7 +1,14 @@
 //! Peripherals.
 
+pub mod clock_config;
+pub mod clock_listeners;
+pub mod config_store;
+
 #[macro_use]
 pub mod system;
 
 #[macro_use]
 pub mod gpio_pins;
+
+#[macro_use]
+pub mod gpio_ports;
@@ -1,18 +1,68 @@
 //! System associated helper functions.
 
-use crate::consts::{HSE_CLK, HSI_CLK};
+use crate::consts::HSI_CLK;
+use crate::drv::flash::{FlashLatency, HALF_CYCLE_MAX_SYSCLK};
+use crate::drv::rcc_ready::ClockError;
+use crate::sys::clock_config::{
+    AhbPrescaler, ApbPrescaler, ClockConfig, ClockConfigBuilder, ClockFrequencies, HseConfig,
+    PllMul, PllSrc, Prediv, SysClkSrc, PCLK1_MAX, PCLK2_MAX,
+};
+use crate::sys::clock_listeners::{ClockChange, ClockPhase};
 use crate::tasks::root::SystemRes;
 //use crate::thr;
+use drone_core::reg::tag::Crt;
 use drone_cortexm::{fib, reg::prelude::*, thr::prelude::*};
-//use drone_stm32_map::periph::sys_tick::SysTickPeriph;
-use drone_core::log;
-use drone_cortexm::swo;
+use drone_stm32_map::{periph::sys_tick::SysTickPeriph, reg};
 use futures::prelude::*;
 
 /// An error returned when a receiver has missed too many ticks.
 #[derive(Debug)]
 pub struct TickOverflow;
 
+/// A clock tree configuration found by [`System::configure_for`].
+#[derive(Clone, Copy, Debug)]
+pub struct ClockSolution {
+    /// The chosen configuration.
+    pub config: ClockConfig,
+    /// Achieved SYSCLK frequency.
+    pub sysclk: u32,
+    /// Achieved AHB clock frequency.
+    pub hclk: u32,
+    /// Achieved APB1 clock frequency.
+    pub pclk1: u32,
+    /// Achieved APB2 clock frequency.
+    pub pclk2: u32,
+    /// Flash wait states required at `sysclk`.
+    pub latency: FlashLatency,
+}
+
+/// SysTick registers.
+///
+/// The tokens are copyable, so the SysTick clock listener can own its own
+/// copy while the root thread keeps using SysTick for delays.
+#[derive(Clone, Copy)]
+pub struct SysTick {
+    /// Control and status register.
+    pub stk_ctrl: reg::stk::Ctrl<Crt>,
+    /// Reload value register.
+    pub stk_load: reg::stk::Load<Crt>,
+    /// Current value register.
+    pub stk_val: reg::stk::Val<Crt>,
+}
+
+impl SysTick {
+    /// Creates a new [`SysTick`].
+    #[inline]
+    pub fn new(periph: SysTickPeriph) -> Self {
+        let SysTickPeriph { stk_ctrl, stk_load, stk_val, .. } = periph;
+        Self {
+            stk_ctrl: stk_ctrl.into_copy(),
+            stk_load: stk_load.into_copy(),
+            stk_val: stk_val.into_copy(),
+        }
+    }
+}
+
 /// System.
 pub struct System {}
 
@@ -24,119 +74,249 @@ impl System {
     }
 
     /// Apply the current clock tree configuration.
-    pub fn apply_clock_config(res: &SystemRes) {
-        res.flash.set_latency(2);
-        res.hsi.init(res);
+    ///
+    /// If a clock doesn't become ready in time, the system is left running
+    /// from HSI with the reset configuration, and the error is returned.
+    ///
+    /// The clock listeners are notified around the switch.
+    pub fn apply_clock_config(res: &mut SystemRes) -> Result<(), ClockError> {
+        let old = System::clock_frequencies(res);
+        let new = res.clock.frequencies();
+        System::notify_clock_change(res, ClockPhase::Before, old, new);
+        let applied = System::try_apply_clock_config(res);
+        if applied.is_err() {
+            System::fall_back_to_hsi(res);
+        }
+        let new = System::clock_frequencies(res);
+        System::notify_clock_change(res, ClockPhase::After, old, new);
+        applied
+    }
+
+    fn notify_clock_change(
+        res: &SystemRes,
+        phase: ClockPhase,
+        old: ClockFrequencies,
+        new: ClockFrequencies,
+    ) {
+        res.clock_listeners.notify(&ClockChange { phase, old, new });
+    }
+
+    fn try_apply_clock_config(res: &SystemRes) -> Result<(), ClockError> {
+        // The most wait states are safe at any SYSCLK.
+        res.flash.set_latency(FlashLatency::Ws2, System::calculate_sysclk(res))?;
+        if res.clock.sysclk() > HALF_CYCLE_MAX_SYSCLK || res.clock.hpre() != AhbPrescaler::Div1 {
+            let hpre = AhbPrescaler::from_bits(res.rcc.read_hpre());
+            res.flash.set_half_cycle(false, System::calculate_sysclk(res), hpre)?;
+        }
+        res.hsi.init(res).root_wait()?;
+        // Start HSE only if used by the SYSCLK or PLL path.
+        if res.clock.uses_hse() {
+            if let Some(hse) = res.clock.hse() {
+                res.hse.init(res, hse.mode).root_wait()?;
+                // Watch the HSE from now on.
+                res.css.enable();
+            }
+        }
         // Start pll only if used as clock source.
-        if res.clksrc == 0b10 {
-            res.pll.init(res);
-            swo::update_prescaler((HSI_CLK/2)*(res.pllmul+2) / log::baud_rate!() - 1);
+        if res.clock.sysclk_src() == SysClkSrc::Pll {
+            res.pll.init(&res.clock);
             System::delay(50, System::calculate_hclk(res), res).root_wait();
-            res.pll.enable();
+            res.pll.enable(res).root_wait()?;
         }
-        res.rcc.init(res);
-        res.flash.set_latency(System::calculate_latency(res));
+        res.flash.check_clock(res.clock.sysclk(), res.clock.hpre())?;
+        res.rcc.init(&res.clock);
+        res.flash.set_latency(System::calculate_latency(res), System::calculate_sysclk(res))?;
+        Ok(())
+    }
+
+    fn fall_back_to_hsi(res: &mut SystemRes) {
+        res.clock = ClockConfig::reset();
+        res.rcc.init(&res.clock);
+        res.css.disable();
+        // Best effort, SYSCLK no longer depends on them.
+        let hclk = System::calculate_hclk(res);
+        res.pll.disable(hclk).ok();
+        res.hse.disable(hclk).ok();
+        res.flash.set_latency(System::latency_for(HSI_CLK), HSI_CLK).ok();
+    }
+
+    /// Finds the clock tree configuration whose HCLK is the closest to
+    /// `target_hz`.
+    ///
+    /// All valid combinations of SYSCLK source, PLL source (HSI/2, or
+    /// HSE/PREDIV if an `hse` is given), PLL multiplication factor and AHB
+    /// prescaler are tried. On a tie the lowest SYSCLK wins. The APB
+    /// prescalers are set to the smallest division which keeps the buses
+    /// within their limits.
+    pub fn configure_for(target_hz: u32, hse: Option<HseConfig>) -> ClockSolution {
+        let mut best: Option<ClockSolution> = None;
+        let mut consider = |builder: ClockConfigBuilder| {
+            for &hpre in AhbPrescaler::ALL.iter() {
+                let builder = builder.hpre(hpre);
+                let hclk = builder.build_unchecked().hclk();
+                let (ppre1, ppre2) = match (
+                    ApbPrescaler::for_max(hclk, PCLK1_MAX),
+                    ApbPrescaler::for_max(hclk, PCLK2_MAX),
+                ) {
+                    (Some(ppre1), Some(ppre2)) => (ppre1, ppre2),
+                    _ => continue,
+                };
+                let config = match builder.ppre1(ppre1).ppre2(ppre2).build() {
+                    Ok(config) => config,
+                    Err(_) => continue,
+                };
+                let candidate = ClockSolution {
+                    config,
+                    sysclk: config.sysclk(),
+                    hclk,
+                    pclk1: config.pclk1(),
+                    pclk2: config.pclk2(),
+                    latency: System::latency_for(config.sysclk()),
+                };
+                let better = match &best {
+                    None => true,
+                    Some(best) => {
+                        let error = |s: &ClockSolution| (s.hclk as i64 - target_hz as i64).abs();
+                        let (new, old) = (error(&candidate), error(best));
+                        new < old || (new == old && candidate.sysclk < best.sysclk)
+                    }
+                };
+                if better {
+                    best = Some(candidate);
+                }
+            }
+        };
+
+        consider(ClockConfig::builder().sysclk_src(SysClkSrc::Hsi));
+        for &pll_mul in PllMul::ALL.iter() {
+            consider(
+                ClockConfig::builder()
+                    .sysclk_src(SysClkSrc::Pll)
+                    .pll(PllSrc::HsiDiv2, pll_mul),
+            );
+        }
+        if let Some(hse) = hse {
+            let builder = ClockConfig::builder().hse(hse.mode, hse.freq);
+            consider(builder.sysclk_src(SysClkSrc::Hse));
+            for &prediv in Prediv::ALL.iter() {
+                for &pll_mul in PllMul::ALL.iter() {
+                    consider(
+                        builder
+                            .sysclk_src(SysClkSrc::Pll)
+                            .pll(PllSrc::HsePrediv, pll_mul)
+                            .prediv(prediv),
+                    );
+                }
+            }
+        }
+
+        // The reset configuration is always valid, so there is a solution.
+        best.unwrap()
     }
 
     /// Resets the RCC.
-    pub fn reset_rcc(res: &SystemRes) {
+    ///
+    /// The reset is always carried out to the end. The first clock which
+    /// didn't stop in time is reported.
+    pub fn reset_rcc(res: &SystemRes) -> Result<(), ClockError> {
+        let old = System::clock_frequencies(res);
+        let new = ClockConfig::reset().frequencies();
+        System::notify_clock_change(res, ClockPhase::Before, old, new);
         res.rcc.reset();
-        res.pll.disable();
+        let hclk = System::calculate_hclk(res);
+        let pll = res.pll.disable(hclk);
         res.pll.reset();
-        res.hsi.reset();
-        swo::update_prescaler(HSI_CLK / log::baud_rate!() - 1);
+        res.css.disable();
+        let hse = res.hse.disable(hclk);
+        // The HSI trim is kept, it may hold a calibration.
+        System::notify_clock_change(res, ClockPhase::After, old, new);
         System::delay(50, System::calculate_hclk(res), res).root_wait();
+        pll.and(hse)
+    }
+
+    /// Brings the system back to the reset configuration after the Clock
+    /// Security System reported an HSE failure.
+    ///
+    /// The hardware has already switched SYSCLK to HSI. This method makes the
+    /// registers and `res.clock` agree again, and returns the new frequencies.
+    pub fn recover_from_clock_fault(res: &mut SystemRes) -> ClockFrequencies {
+        res.clock = ClockConfig::reset();
+        if let Err(err) = System::reset_rcc(res).and_then(|()| System::apply_clock_config(res)) {
+            println!("Clock recovery incomplete: {:?}", err);
+        }
+        System::clock_frequencies(res)
     }
 
     /// Set flash read access latency.
     // To correctly read data from Flash memory, the number of
     // wait states (LATENCY) must be correctly programmed
-    pub fn calculate_latency(res: &SystemRes) -> u32 {
-        let mut hclk: u32;
-        println!("SWS field value config: {}",res.clksrc);
-        match res.clksrc {
-            0b00 => {
-                // HSI oscillator used as system clock.
-                hclk = HSI_CLK;
-            }
-            0b01 => {
-                // HSE oscillator used as system clock.
-                hclk = HSE_CLK;
-            }
-            0b10 => {
-                // PLL used as system clock.
-                println!("PLLSRC value config: {}",res.pllsrc);
-                match res.pllsrc {
-                    0b00 => {
-                        // HSI/2 selected as PLL input clock. 
-                        hclk = HSI_CLK >> 1;
-                        println!("PLL input: {} MHz", hclk);
-                    }
-                    0b01=> {
-                        // HSE/PREDIV selected as PLL input clock 
-                        hclk = HSE_CLK / (res.prediv + 1);
-                    }
-                    _ => {
-                        // No clock sent to PLL.
-                        hclk = 0;
-                    }
-                }
-                // Multiply by decoded value of main PLL multiplication factor.
-                println!("res.pllmul + 2 = {}", res.pllmul + 2);
-                hclk = hclk * (res.pllmul + 2); 
-            }
-            _ => hclk = HSI_CLK,
-        }
+    pub fn calculate_latency(res: &SystemRes) -> FlashLatency {
+        let sysclk = res.clock.sysclk();
+        println!("sysclk for latency {}", sysclk);
+        System::latency_for(sysclk)
+    }
 
-        // Return the correct number of wait states according to ref manual.
-        println!("hclk for latency {}", hclk);
-        if hclk <= 24_000_000 {
-            0b00
-        }
-        else if hclk <= 48_000_000 {
-            0b01
-        }
-        else {
-            0b10
-        }
+    /// Returns the flash wait states required at `sysclk`.
+    pub fn latency_for(sysclk: u32) -> FlashLatency {
+        // Return the correct number of wait states according to ref manual,
+        // which bases them on SYSCLK, not on the prescaled HCLK.
+        FlashLatency::for_sysclk(sysclk)
     }
 
+    /// Returns the current AHB clock frequency.
     pub fn calculate_hclk(res: &SystemRes) -> u32 {
-        let mut hclk: u32;
+        System::clock_frequencies(res).hclk
+    }
+
+    /// Returns the current bus and timer clock frequencies, derived from
+    /// the live register values.
+    pub fn clock_frequencies(res: &SystemRes) -> ClockFrequencies {
+        ClockFrequencies::new(
+            System::calculate_sysclk(res),
+            AhbPrescaler::from_bits(res.rcc.read_hpre()),
+            ApbPrescaler::from_bits(res.rcc.read_ppre1()),
+            ApbPrescaler::from_bits(res.rcc.read_ppre2()),
+        )
+    }
+
+    /// Returns the current system clock frequency.
+    pub fn calculate_sysclk(res: &SystemRes) -> u32 {
+        let mut sysclk: u32;
         // Check which clock source is used as system clock.
         println!("SWS field value: {}",res.rcc.read_sws());
-        match res.rcc.read_sws() {
-            0b00 => {
+        match SysClkSrc::from_bits(res.rcc.read_sws()) {
+            Some(SysClkSrc::Hsi) => {
                 // HSI oscillator used as system clock.
-                hclk = HSI_CLK;
+                sysclk = HSI_CLK;
             }
-            0b01 => {
+            Some(SysClkSrc::Hse) => {
                 // HSE used as system clock.
-                hclk = HSE_CLK;
+                sysclk = res.clock.hse_freq();
             }
-            0b10 => {
+            Some(SysClkSrc::Pll) => {
                 // PLL used as system clock.
-                match res.pll.read_pllsrc() {
-                    0b00 => {
+                match PllSrc::from_bits(res.pll.read_pllsrc()) {
+                    Some(PllSrc::HsiDiv2) => {
                         // HSI/2 selected as PLL input clock. 
-                        hclk = HSI_CLK / 2;
+                        sysclk = HSI_CLK / 2;
                     }
-                    0b01 => {
+                    Some(PllSrc::HsePrediv) => {
                         // HSE/PREDIV selected as PLL input clock 
-                        hclk = HSE_CLK / (res.prediv + 1);
+                        sysclk = res.clock.hse_freq()
+                            / Prediv::from_bits(res.pll.read_prediv()).divisor();
                     }
-                    _ => {
-                        // No clock sent to PLL, PLLSAI1 and PLLSAI2
-                        hclk = 0;
+                    None => {
+                        // No clock sent to PLL.
+                        sysclk = 0;
                     }
                 }
                 // Multiply by value of main PLL multiplication factor.
                 println!("PLLMUL field value: {}",res.pll.read_pllmul());
-                hclk = hclk * (res.pll.read_pllmul() + 2);
+                sysclk = sysclk * PllMul::from_bits(res.pll.read_pllmul()).factor();
             }
-            _ => hclk = HSI_CLK,
+            None => sysclk = HSI_CLK,
         }
-        hclk
+        sysclk
     }
 
     /// Millisecond delay.
@@ -160,3 +340,89 @@ impl System {
         tick_stream.next().await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::HSE_STLINK_MCO_CLK;
+    use crate::sys::clock_config::HseMode;
+
+    const HSE_8MHZ: HseConfig = HseConfig { mode: HseMode::Bypass, freq: HSE_STLINK_MCO_CLK };
+
+    #[test]
+    fn exact_hit_from_hsi() {
+        let solution = System::configure_for(64_000_000, None);
+        assert_eq!(solution.hclk, 64_000_000);
+        assert_eq!(solution.config.sysclk_src(), SysClkSrc::Pll);
+        assert_eq!(solution.config.pll_src(), PllSrc::HsiDiv2);
+        assert_eq!(solution.config.pll_mul(), PllMul::Mul16);
+        assert_eq!(solution.config.hpre(), AhbPrescaler::Div1);
+    }
+
+    #[test]
+    fn closest_when_out_of_reach() {
+        // HSI/2 x 16 is the fastest without an HSE.
+        let solution = System::configure_for(72_000_000, None);
+        assert_eq!(solution.hclk, 64_000_000);
+    }
+
+    #[test]
+    fn tie_prefers_lowest_sysclk() {
+        // HSI alone, PLL 16 MHz / 2, PLL 32 MHz / 4, ... all give 8 MHz.
+        let solution = System::configure_for(8_000_000, None);
+        assert_eq!(solution.hclk, 8_000_000);
+        assert_eq!(solution.sysclk, 8_000_000);
+        assert_eq!(solution.config.sysclk_src(), SysClkSrc::Hsi);
+
+        let solution = System::configure_for(4_000_000, None);
+        assert_eq!(solution.hclk, 4_000_000);
+        assert_eq!(solution.sysclk, 8_000_000);
+        assert_eq!(solution.config.hpre(), AhbPrescaler::Div2);
+    }
+
+    #[test]
+    fn hse_path() {
+        let solution = System::configure_for(72_000_000, Some(HSE_8MHZ));
+        assert_eq!(solution.hclk, 72_000_000);
+        assert_eq!(solution.config.sysclk_src(), SysClkSrc::Pll);
+        assert_eq!(solution.config.pll_src(), PllSrc::HsePrediv);
+        assert_eq!(solution.config.prediv(), Prediv::Div1);
+        assert_eq!(solution.config.pll_mul(), PllMul::Mul9);
+        assert!(solution.config.uses_hse());
+    }
+
+    #[test]
+    fn hse_not_used_when_hsi_is_as_good() {
+        let solution = System::configure_for(64_000_000, Some(HSE_8MHZ));
+        assert_eq!(solution.hclk, 64_000_000);
+        assert_eq!(solution.sysclk, 64_000_000);
+        assert!(!solution.config.uses_hse());
+    }
+
+    #[test]
+    fn apb_limits() {
+        let solution = System::configure_for(72_000_000, Some(HSE_8MHZ));
+        assert_eq!(solution.pclk1, 36_000_000);
+        assert_eq!(solution.config.ppre1(), ApbPrescaler::Div2);
+        assert_eq!(solution.pclk2, 72_000_000);
+        assert_eq!(solution.config.ppre2(), ApbPrescaler::Div1);
+
+        for target in (1_000_000..=72_000_000).step_by(500_000) {
+            let solution = System::configure_for(target, Some(HSE_8MHZ));
+            assert!(solution.pclk1 <= PCLK1_MAX, "PCLK1 at {}", target);
+            assert!(solution.pclk2 <= PCLK2_MAX, "PCLK2 at {}", target);
+        }
+    }
+
+    #[test]
+    fn latency_follows_sysclk() {
+        // 36 MHz / 8: HCLK alone would allow zero wait states.
+        let solution = System::configure_for(4_500_000, None);
+        assert_eq!(solution.hclk, 4_500_000);
+        assert_eq!(solution.sysclk, 36_000_000);
+        assert_eq!(solution.latency, FlashLatency::Ws1);
+
+        assert_eq!(System::configure_for(8_000_000, None).latency, FlashLatency::Ws0);
+        assert_eq!(System::configure_for(64_000_000, None).latency, FlashLatency::Ws2);
+    }
+}
@@ -3,16 +3,25 @@
 use crate::consts::HSI_CLK;
 use crate::{
     drv::{
+        backup::{Backup, BackupSlot, BackupValue},
+        css::{ClockFault, Css},
         exti::{ExtiDrv, ExtiSetup},
         flash::Flash,
-        gpio::GpioHead,
+        hse::Hse,
         hsi::Hsi,
         lse::Lse,
         pll::Pll,
         rcc::Rcc,
+        rcc_ready::RccReady,
+        rtc::{Rtc, RtcClock},
+    },
+    sys::{
+        clock_config::{ApbPrescaler, ClockConfig, PllMul, PllSrc, SysClkSrc},
+        clock_listeners::{swo_listener, sys_tick_listener, ClockListeners},
+        config_store::{keys, ConfigStore},
+        gpio_pins::{GpioPins, GpioPinsRes},
+        system::{SysTick, System},
     },
-    drv_gpio_pins,
-    sys::{gpio_pins::GpioPins, system::System},
     thr,
     thr::{Thrs, ThrsInit},
     Regs,
@@ -23,8 +32,7 @@ use drone_cortexm::processor::fpu_init;
 use drone_cortexm::{fib, reg::prelude::*, thr::prelude::*};
 use drone_stm32_map::periph::exti::periph_exti5;
 use drone_stm32_map::periph::exti::Exti5;
-use drone_stm32_map::periph::gpio::periph_gpio_b_head;
-use drone_stm32_map::periph::sys_tick::{periph_sys_tick, SysTickPeriph};
+use drone_stm32_map::periph::sys_tick::periph_sys_tick;
 
 use futures::prelude::*;
 use futures::select_biased;
@@ -32,6 +40,7 @@ use futures::select_biased;
 enum Event {
     Tick,
     Push,
+    ClockFault,
 }
 
 enum ClockMode {
@@ -40,8 +49,55 @@ enum ClockMode {
     High64MHz,
 }
 
-enum Led {
-    GreenLed = 1,
+/// The clock mode last selected by the user, kept across resets. It is also
+/// kept in the config store under [`keys::CLOCK_MODE`] to survive power loss.
+const CLOCK_MODE: BackupSlot<ClockMode> = BackupSlot::new(1);
+
+/// LED on/off interval in ticks at 4 MHz HCLK, unless the config store holds
+/// another one under [`keys::LED_TIMING`].
+const DEFAULT_LED_TIMING: u32 = 40;
+
+impl BackupValue for ClockMode {
+    fn to_word(&self) -> u32 {
+        match self {
+            ClockMode::Reset8MHz => 8,
+            ClockMode::Medium32MHz => 32,
+            ClockMode::High64MHz => 64,
+        }
+    }
+
+    fn from_word(word: u32) -> Option<Self> {
+        match word {
+            8 => Some(ClockMode::Reset8MHz),
+            32 => Some(ClockMode::Medium32MHz),
+            64 => Some(ClockMode::High64MHz),
+            _ => None,
+        }
+    }
+}
+
+impl ClockMode {
+    /// Returns the clock tree configuration for the mode.
+    fn clock_config(&self) -> ClockConfig {
+        match self {
+            // Use HSI 8MHz, no PLL.
+            ClockMode::Reset8MHz => ClockConfig::reset(),
+            // HSI is PLL clock input, use PLL output 32 MHz.
+            ClockMode::Medium32MHz => ClockConfig::builder()
+                .sysclk_src(SysClkSrc::Pll)
+                .pll(PllSrc::HsiDiv2, PllMul::Mul8)
+                .build()
+                .expect("invalid 32 MHz clock configuration"),
+            // HSI is PLL clock input, use PLL output 64 MHz.
+            // APB1 must not exceed 36 MHz.
+            ClockMode::High64MHz => ClockConfig::builder()
+                .sysclk_src(SysClkSrc::Pll)
+                .pll(PllSrc::HsiDiv2, PllMul::Mul16)
+                .ppre1(ApbPrescaler::Div2)
+                .build()
+                .expect("invalid 64 MHz clock configuration"),
+        }
+    }
 }
 
 /// An error returned when a receiver has missed too many ticks.
@@ -50,32 +106,36 @@ pub struct TickOverflow;
 
 /// System Resources
 pub struct SystemRes {
-    pub sys_tick: SysTickPeriph,
+    pub sys_tick: SysTick,
     pub thr_sys_tick: thr::SysTick,
+    pub thr_rcc: thr::Rcc,
     pub pll: Pll,
     pub hsi: Hsi,
+    pub hse: Hse,
+    pub css: Css,
     pub lse: Lse,
     pub rcc: Rcc,
+    pub rcc_ready: RccReady,
     pub flash: Flash,
-    pub pllmul: u32,
-    pub clksrc: u32,
-    pub pllsrc: u32,
-    pub hpre: u32,
-    pub prediv: u32,
+    pub clock: ClockConfig,
+    pub clock_listeners: ClockListeners,
 }
 
 #[allow(unused_labels)]
 #[inline(never)]
 pub fn handler(reg: Regs, thr_init: ThrsInit) {
-    let mut clock_mode = ClockMode::High64MHz;
+    // Start in the mode selected before the last reset.
+    let backup = Backup::new(periph_backup!(reg));
+    let mut clock_mode = backup.load(&CLOCK_MODE).unwrap_or(ClockMode::High64MHz);
 
     let (thr, scb) = thr::init_extended(thr_init);
     thr.hard_fault.add_once(|| panic!("Hard Fault"));
 
     // Allocate the clock control resources.
     let mut res = SystemRes {
-        sys_tick: periph_sys_tick!(reg),
+        sys_tick: SysTick::new(periph_sys_tick!(reg)),
         thr_sys_tick: thr.sys_tick,
+        thr_rcc: thr.rcc,
         // ----------------------
         // -- Clocks.
         // The internal PLLs can be used to multiply the HSI or HSE
@@ -83,82 +143,58 @@ pub fn handler(reg: Regs, thr_init: ThrsInit) {
         pll: Pll::new(periph_pll!(reg)),
         // The HSI clock signal is generated from an internal 8 MHz RC Oscillator.
         hsi: Hsi::new(periph_hsi!(reg)),
+        // The HSE clock, fed by the ST-LINK MCO in bypass mode if SB4 is ON.
+        hse: Hse::new(periph_hse!(reg)),
+        // The clock security system, watching the HSE.
+        css: Css::new(periph_css!(reg)),
         // The LSE clock (32.768K oscillator, not used in this crate.)
         lse: Lse::new(periph_lse!(reg)),
         // The RCC component.
         rcc: Rcc::new(periph_rcc!(reg)),
+        // The clock ready interrupts, handled on the RCC thread.
+        rcc_ready: RccReady::new(periph_rcc_ready!(reg)),
         // The flash component,
         flash: Flash::new(periph_flash!(reg)),
         // ----------------------
-        // -- Factors and selectors.
-        // CAUTION: Setting wrong values may make your system unusable.
-        // Read the reference manual for detailed information.
-        //
-        // PLL multiplication factor.
-        // Possible values for pllmul:
-        // Caution: The PLL output frequency must not exceed 72 MHz.
-        // 0000: PLL input clock x 2
-        // 0001: PLL input clock x 3
-        // 0010: PLL input clock x 4
-        // 0011: PLL input clock x 5
-        // 0100: PLL input clock x 6
-        // 0101: PLL input clock x 7
-        // 0110: PLL input clock x 8
-        // 0111: PLL input clock x 9
-        // 1000: PLL input clock x 10
-        // 1001: PLL input clock x 11
-        // 1010: PLL input clock x 12
-        // 1011: PLL input clock x 13
-        // 1100: PLL input clock x 14
-        // 1101: PLL input clock x 15
-        // 1110: PLL input clock x 16
-        // 1111: Not applicable 
-        pllmul: 0b1110,  // Field RCC_CFGR PLLMUL in ref. manual RM0316. 
-
-        // System clock switch.
-        // Possible values for clksrc:
-        // 00: HSI oscillator used as system clock. 
-        // 01: HSE oscillator used as system clock. 
-        // 10: PLL used as system clock 
-        // 11: Not applicable. 
-        clksrc: 0b10, // Field RCC_CFGR SW in ref. manual RM0316.
-        //
-        // Possible values for pllsrc:
-        // Caution: Different values for STM32F303xD/E and STM32F398xE!
-        // 00: HSI/2 selected as PLL input clock. 
-        // 01: HSE/PREDIV selected as PLL input clock 
-        // 10: Reserved. 
-        // 11: Reserved. 
-        pllsrc: 0b00, // Field RCC_CFGR PLLSRC in ref. manual RM0316.
-
-        // Division factor of the AHB clock (AHB prescaler).
-        // Possible values for hpre:
-        // 0xxx: SYSCLK not divided
-        // 1000: SYSCLK divided by 2
-        // 1001: SYSCLK divided by 4
-        // 1010: SYSCLK divided by 8
-        // 1011: SYSCLK divided by 16
-        // 1100: SYSCLK divided by 64
-        // 1101: SYSCLK divided by 128
-        // 1110: SYSCLK divided by 256
-        // 1111: SYSCLK divided by 512
-        hpre: 0b0000, // Field RCC_CFGR HPRE in ref. manual RM0316.
-
-        // PREDIV division factor.
-        prediv: 0b000, // Field RCC_CFGR2 PREDIV in ref. manual RM0316.
+        // -- Clock tree configuration.
+        clock: clock_mode.clock_config(),
+        // Subsystems to keep in line with clock switches.
+        clock_listeners: ClockListeners::new(),
     };
+    res.clock_listeners.register(swo_listener);
+    res.clock_listeners.register(sys_tick_listener(res.sys_tick));
 
     swo::flush();
     swo::update_prescaler(HSI_CLK / log::baud_rate!() - 1);
     System::delay(100, HSI_CLK, &res).root_wait();
 
-    // The on-board user LED is connected to GPIO bank B.
+    // Apply the HSI trimming value of an earlier calibration, if any.
+    if let Some(trim) = load_config(&res, keys::HSI_TRIM) {
+        res.hsi.set_trim(trim);
+    }
+    // The backup registers are lost on power loss, fall back to the mode
+    // kept in the config store.
+    if backup.load(&CLOCK_MODE).is_none() {
+        if let Some(mode) = load_config(&res, keys::CLOCK_MODE).and_then(ClockMode::from_word) {
+            clock_mode = mode;
+            res.clock = clock_mode.clock_config();
+        }
+    }
+    let led_timing = load_config(&res, keys::LED_TIMING).unwrap_or_else(|| {
+        store_config(&res, keys::LED_TIMING, DEFAULT_LED_TIMING);
+        DEFAULT_LED_TIMING
+    });
+
     // Create register and pins mapping component.
-    let gpio_pins = drv_gpio_pins!(reg);
-    let mut gpio_b = GpioHead::new(periph_gpio_b_head!(reg));
-    // Enable and initialize.
-    let gpio_b_en = gpio_b.enable();
-    gpio_pins.init(gpio_b_en.inventory_token());
+    let gpio_pins_res = drv_gpio_pins!(reg);
+    let mut gpio_ports = drv_gpio_ports!(reg);
+    // Enable the only port in use and initialize.
+    let gpio_b = gpio_ports.b.enable();
+    let gpio_pins = GpioPins::init(gpio_pins_res, gpio_b.inventory_token());
+    // Keep the LED pin from being reconfigured by stray code.
+    if let Err(err) = gpio_b.lock(gpio_pins.led.mask()) {
+        println!("LED pin lock failed: {:?}", err);
+    }
 
     scb.scb_ccr_div_0_trp.set_bit();
     unsafe {
@@ -171,6 +207,13 @@ pub fn handler(reg: Regs, thr_init: ThrsInit) {
     // Setup fault handlers.
     thr.hard_fault.add_once(|| panic!("Hard Fault"));
 
+    // The RTC keeps the wall-clock time across clock mode switches. The
+    // NUCLEO-F303K8 has no LSE crystal, so it runs from the LSI.
+    let rtc = Rtc::new(periph_rtc!(reg));
+    if let Err(err) = rtc.init(&res, RtcClock::Lsi).root_wait() {
+        println!("RTC init failed: {:?}", err);
+    }
+
     // Exti configuration for the user button.
     // There is no user button on the Nucleo-F303K8,
     // but we use the PB4 pin to emulate it.
@@ -182,49 +225,91 @@ pub fn handler(reg: Regs, thr_init: ThrsInit) {
         rising: true,   // don't trigger the interrupt on a rising edge.
     });
 
+    // Listen to HSE failures for the whole runtime, so the NMI is always
+    // acknowledged.
+    let mut fault_stream = res.css.create_fault_stream(thr.nmi);
+
     'user_button_pressed: loop {
         // Reset the clock control registers to their default.
-        System::reset_rcc(&res);
+        if let Err(err) = System::reset_rcc(&res) {
+            println!("Clock reset incomplete: {:?}", err);
+        }
 
         // Apply the current clock tree configuration.
-        System::apply_clock_config(&res);
+        if let Err(err) = System::apply_clock_config(&mut res) {
+            println!("Clock setup failed: {:?}, running on HSI", err);
+            clock_mode = ClockMode::Reset8MHz;
+        }
 
         // Calculate the configured clock speed.
-        let hclk = System::calculate_hclk(&res);
+        let freqs = System::clock_frequencies(&res);
+        let hclk = freqs.hclk;
 
-        swo::flush();
-        swo::update_prescaler(hclk / log::baud_rate!() - 1);
         System::delay(50, hclk, &res).root_wait();
 
-        println!("Running at {} MHz", hclk);
+        println!("Running at {} Hz", hclk);
+        println!("APB1 at {} Hz, APB2 at {} Hz", freqs.pclk1, freqs.pclk2);
+        match rtc.date_time() {
+            Ok(now) => {
+                println!("RTC time {:02}:{:02}:{:02}", now.hours, now.minutes, now.seconds)
+            }
+            Err(err) => println!("RTC read failed: {:?}", err),
+        }
 
-        listen(&res, &thr, &exti5, &gpio_pins, hclk).root_wait();
+        if let Event::ClockFault =
+            listen(&res, &thr, &exti5, &gpio_pins, &mut fault_stream, hclk, led_timing)
+                .root_wait()
+        {
+            let freqs = System::recover_from_clock_fault(&mut res);
+            println!("HSE failure, running on HSI at {} Hz", freqs.hclk);
+            clock_mode = ClockMode::Reset8MHz;
+            continue 'user_button_pressed;
+        }
 
         // Set different configuration for the clock tree
         // depending on current configuration
         match clock_mode {
             ClockMode::Reset8MHz => {
                 clock_mode = ClockMode::Medium32MHz; // <- new mode.
-                res.pllsrc = 0b00; // HSI is PLL clock input.
-                res.clksrc = 0b10; // Use PLL output 32 MHz.
-                res.pllmul = 0b0110;
                 System::delay(50, 8_000_000, &res).root_wait();
             }
             ClockMode::Medium32MHz => {
                 clock_mode = ClockMode::High64MHz; // <- new mode.
-                res.pllsrc = 0b00; // HSI is PLL clock input.
-                res.clksrc = 0b10; // Use PLL output 64 MHz
-                res.pllmul = 0b1110;
                 System::delay(50, 32_000_000, &res).root_wait();
             }
             ClockMode::High64MHz => {
                 clock_mode = ClockMode::Reset8MHz; // <- new mode.
-                res.pllsrc = 0b00; // No PLL.
-                res.clksrc = 0b00; // Use HSI 8MHz.
-                res.pllmul = 0b0000;
                 System::delay(20, 64_000_0000, &res).root_wait();
             }
         }
+        res.clock = clock_mode.clock_config();
+        backup.store(&res, &CLOCK_MODE, &clock_mode);
+        store_config(&res, keys::CLOCK_MODE, clock_mode.to_word());
+    }
+}
+
+/// Reads the `u32` value of `key` from the config store.
+fn load_config(res: &SystemRes, key: u16) -> Option<u32> {
+    let mut config = ConfigStore::new(&res.flash);
+    match config.mount().and_then(|()| config.read_u32(key)) {
+        Ok(value) => value,
+        Err(err) => {
+            println!("Config store unavailable: {:?}", err);
+            None
+        }
+    }
+}
+
+/// Writes the `u32` value of `key` to the config store, unless it is already
+/// there, to spare the flash.
+fn store_config(res: &SystemRes, key: u16, value: u32) {
+    let mut config = ConfigStore::new(&res.flash);
+    let written = config.mount().and_then(|()| match config.read_u32(key)? {
+        Some(old) if old == value => Ok(()),
+        _ => config.write_u32(key, value),
+    });
+    if let Err(err) = written {
+        println!("Config store write failed: {:?}", err);
     }
 }
 
@@ -233,7 +318,9 @@ async fn listen(
     thr: &Thrs,
     exti5: &ExtiDrv<Exti5, thr::Exti95>,
     gpio_pins: &GpioPins,
+    fault_stream: &mut (impl Stream<Item = ClockFault> + Unpin),
     hclk: u32,
+    led_timing: u32,
 ) -> Event {
     println!("Enter listen, hclk={}", hclk);
     // Attach a listener that will notify us on user button pressed.
@@ -275,7 +362,7 @@ async fn listen(
     });
 
     let mut green_led_on = true;
-    gpio_pins.output(Led::GreenLed as u8, true); // Start with red led ON.
+    gpio_pins.led.set_high(); // Start with red led ON.
 
     // Enable the interrupt for the user button.
     thr.exti_9_5.enable_int();
@@ -290,14 +377,21 @@ async fn listen(
     let doubleclick_ival = 4;
 
     // This is dependent on mcu speed:
-    let ticks_ival: u32 = 40 / (hclk / 4_000_000);
+    let ticks_ival: u32 = led_timing / (hclk / 4_000_000);
 
-    'blinky: loop {
+    // Every exit goes through the end of the loop, so the button interrupt is
+    // always disabled when leaving.
+    let event = 'blinky: loop {
         let evt = select_biased! {
+            _f = fault_stream.next().fuse() => Event::ClockFault,
             _p = button_stream.next().fuse() => Event::Push,
             _t = tick_stream.next().fuse() => Event::Tick,
         };
         match evt {
+            Event::ClockFault => {
+                println!("Clock fault");
+                break 'blinky Event::ClockFault;
+            }
             Event::Tick => {
                 if debounce_protection > i16::MIN {
                     debounce_protection = debounce_protection - 1;
@@ -307,7 +401,7 @@ async fn listen(
                 };
                 if debounce_protection == 0 && doubleclick_protection >= doubleclick_ival {
                     println!("Switch to new speed");
-                    break 'blinky;
+                    break 'blinky Event::Push;
                 }
                 // The low and the high interval is 'ticks_ival' ticks.
                 ticks_cnt = ticks_cnt + 1;
@@ -317,12 +411,12 @@ async fn listen(
                         true => {
                             println!("LED off");
                             green_led_on = false;
-                            gpio_pins.output(Led::GreenLed as u8, false);
+                            gpio_pins.led.set_low();
                         }
                         _ => {
                             println!("LED on");
                             green_led_on = true;
-                            gpio_pins.output(Led::GreenLed as u8, true);
+                            gpio_pins.led.set_high();
                         }
                     }
                 }
@@ -342,6 +436,7 @@ async fn listen(
                 }
             }
         }
-    }
-    Event::Push
+    };
+    thr.exti_9_5.disable_int();
+    event
 }
@@ -23,16 +23,32 @@ thr! {
 
     threads => {
         exceptions => {
+            /// Non-maskable interrupt, raised by the Clock Security System.
+            pub nmi;
             /// All classes of faults.
             pub hard_fault;
             /// System tick timer.
             pub sys_tick;
         };
         interrupts => {
+            /// RTC wakeup timer interrupt through EXTI line 20.
+            3: pub rtc_wkup;
             /// RCC global interrupt.
             5: pub rcc;
+            /// EXTI Line 0 interrupt.
+            6: pub exti0;
+            /// EXTI Line 1 interrupt.
+            7: pub exti1;
+            /// EXTI Line 2 and touch sensing interrupt.
+            8: pub exti2_tsc;
+            /// EXTI Line 3 interrupt.
+            9: pub exti3;
+            /// EXTI Line 4 interrupt.
+            10: pub exti4;
             /// EXTI Line 5(to9) interrupt.
             23: pub exti9_5;
+            /// EXTI Line 10(to15) interrupt.
+            40: pub exti15_10;
         };
     };
 }